@@ -55,7 +55,7 @@ impl PatriciaNode {
             }
             (Some(&p), None) => {
                 let mut new_node = PatriciaNode::new(&prefix_chars.collect::<String>());
-                new_node.children = mem::replace(&mut self.children, HashMap::new());
+                new_node.children = mem::take(&mut self.children);
 
                 self.prefix = common_prefix;
                 self.children.insert(p, Box::new(new_node));
@@ -65,7 +65,7 @@ impl PatriciaNode {
             (Some(&p), Some(&k)) => {
                 let new_child_key = PatriciaNode::new(&key_chars.collect::<String>());
                 let mut new_child_prefix = PatriciaNode::new(&prefix_chars.collect::<String>());
-                new_child_prefix.children = mem::replace(&mut self.children, HashMap::new());
+                new_child_prefix.children = mem::take(&mut self.children);
 
                 self.prefix = common_prefix;
                 self.is_leaf = false;
@@ -109,10 +109,15 @@ mod tests {
 
         assert_eq!(tree.prefix, "");
         assert_eq!(
-            tree.children
-                .iter()
-                .map(|(_, child)| child.prefix.clone())
-                .collect::<Vec<String>>(),
+            {
+                let mut prefixes = tree
+                    .children
+                    .values()
+                    .map(|child| child.prefix.clone())
+                    .collect::<Vec<String>>();
+                prefixes.sort();
+                prefixes
+            },
             vec!["apple".to_string(), "tea".to_string(),]
         );
 
@@ -1,15 +1,21 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use clap::Parser;
 use colored::Colorize;
 use console::Term;
-use log::info;
+use log::{info, warn};
 use patricia_tree::PatriciaNode;
-use snails_machine::{Config, State, Transition, TuringMachine};
+use regex::Regex;
+use snails_machine::{
+    Alphabet, Breakpoint, Checkpoint, Config, GridTape, GridTuringMachine, OutputMode, Status,
+    Step, StepEvent, Symbol, Tape, TapeBackend, TapeModel, TuringMachine, TuringMachineBuilder,
+};
 use std::{
-    cell::RefCell,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fs,
     io::{stdout, Write as _},
-    rc::Rc,
+    num::NonZeroUsize,
+    slice,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Debug, Parser)]
@@ -31,12 +37,114 @@ struct Args {
     #[arg(long = "snail")]
     is_snail_mode: bool,
 
-    /// Whether to show step-by-step execution
+    /// Whether to show step-by-step execution. Press Backspace to go back a step
     #[arg(short = 'S', long = "step-by-step")]
     is_step_by_step: bool,
 
+    /// Pause step-by-step execution only at steps matching a breakpoint: state=NAME, symbol=NAME,
+    /// step=N or tape=PATTERN, where `?` and `*` in PATTERN match one and any number of symbols.
+    /// Can be given more than once, and implies --step-by-step
+    #[arg(long = "break", value_name = "CONDITION")]
+    breakpoints: Vec<Breakpoint>,
+
+    /// Maximum number of steps to execute before giving up
+    #[arg(long = "max-steps")]
+    max_steps: Option<usize>,
+
+    /// Maximum execution time in seconds before giving up
+    #[arg(long = "timeout")]
+    timeout: Option<f64>,
+
+    /// Whether to stop when the machine repeats a configuration
+    #[arg(long = "detect-loop")]
+    detect_loop: bool,
+
+    /// Whether to simulate the machine as a nondeterministic Turing machine
+    #[arg(short = 'n', long = "nondeterministic")]
+    is_nondeterministic: bool,
+
+    /// Maximum number of branches to keep in nondeterministic mode
+    #[arg(long = "max-frontier", default_value_t = 100_000)]
+    max_frontier: usize,
+
+    /// Tape model (two-way, one-way, one-way-strict or lba). Overrides the definition file
+    #[arg(long = "tape-model")]
+    tape_model: Option<TapeModel>,
+
+    /// Initial head position relative to the first input cell. Overrides the definition file.
+    /// The head can also be marked in TAPE with brackets, as in 10[1]01
+    #[arg(long = "head", allow_negative_numbers = true)]
+    head: Option<isize>,
+
+    /// Tape backend (deque or sparse). Sparse tapes only store non-blank cells
+    #[arg(long = "tape-backend", default_value = "deque")]
+    tape_backend: TapeBackend,
+
+    /// Save a checkpoint every given number of steps
+    #[arg(long = "checkpoint-every")]
+    checkpoint_every: Option<NonZeroUsize>,
+
+    /// Path to the checkpoint file to save
+    #[arg(long = "checkpoint-file", default_value = "./checkpoint.json")]
+    checkpoint_file: String,
+
+    /// Whether to run with the fast-forward engine, which jumps over sweeps across runs of equal
+    /// cells. Only the final configuration is shown
+    #[arg(long = "fast-forward")]
+    fast_forward: bool,
+
+    /// Number of cells the fast-forward engine groups into a single macro symbol
+    #[arg(long = "macro-cells", default_value_t = 1, requires = "fast_forward")]
+    macro_cells: usize,
+
+    /// Print only the result left on the tape instead of the execution: trim (every cell but the
+    /// blanks at both ends) or head (from the head up to the first blank)
+    #[arg(long = "output", value_name = "MODE")]
+    output: Option<OutputMode>,
+
+    /// Index of the tape to read the result from with --output
+    #[arg(long = "output-tape", default_value_t = 0, requires = "output")]
+    output_tape: usize,
+
+    /// Seed for choosing among transitions by their weights. Runs with the same seed take the same
+    /// transitions. Machines with weighted transitions get a random seed if none is given
+    #[arg(long = "seed", conflicts_with = "is_nondeterministic")]
+    seed: Option<u64>,
+
+    /// Run the machine the given number of times with different seeds and report how likely it
+    /// is to accept
+    #[arg(
+        long = "trials",
+        conflicts_with_all = [
+            "is_nondeterministic",
+            "is_step_by_step",
+            "breakpoints",
+            "fast_forward",
+            "output",
+            "checkpoint_every",
+            "resume",
+        ]
+    )]
+    trials: Option<usize>,
+
+    /// Oracle answering the queries of a machine with an [oracle] section: regex:PATTERN (whether
+    /// the query matches the regular expression), machine:FILE (whether the machine in FILE accepts
+    /// the query within --oracle-steps steps) or table:FILE (a TOML table from queries to true or
+    /// false, where missing queries are answered false)
+    #[arg(long = "oracle", value_name = "KIND:ARG")]
+    oracle: Option<String>,
+
+    /// Maximum number of steps a machine oracle takes to answer a query before answering no
+    #[arg(long = "oracle-steps", default_value_t = 10_000)]
+    oracle_steps: usize,
+
+    /// Path to a checkpoint file to resume from
+    #[arg(long = "resume")]
+    resume: Option<String>,
+
     /// Initial tape content
-    tape: String,
+    #[arg(required_unless_present = "resume")]
+    tape: Option<String>,
 }
 
 fn main() -> Result<()> {
@@ -47,78 +155,338 @@ fn main() -> Result<()> {
     info!("Show separator: {}", args.show_separator);
     info!("Snail mode: {}", args.is_snail_mode);
     info!("Step-by-step mode: {}", args.is_step_by_step);
+    if let Some(max_steps) = args.max_steps {
+        info!("Max steps: {}", max_steps);
+    }
+    if let Some(timeout) = args.timeout {
+        info!("Timeout: {}s", timeout);
+    }
+    info!("Loop detection: {}", args.detect_loop);
+    info!("Nondeterministic mode: {}", args.is_nondeterministic);
+    info!("Fast-forward mode: {}", args.fast_forward);
     info!(
         "Loading Turing machine definition from \"{}\"...",
         args.machine_file_path
     );
 
-    let config = Config::read_from_file(args.machine_file_path)?;
-    let mut tm = turing_machine_from_config(&config, args.tape)?;
-    while let Some(_) = tm.next() {}
+    let mut config = Config::read_from_file(&args.machine_file_path)?;
+    if let Some(tape_model) = args.tape_model {
+        config.tape_model = tape_model;
+    }
+    if let Some(head) = args.head {
+        let tape = args.tape.as_deref().unwrap_or_default();
+        let alphabet = tape_alphabet(&config);
+        let alphabet = alphabet.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+        if parse_tape(tape, alphabet)?.1.is_some() {
+            return Err(anyhow!(
+                "The head position is given both by --head and by brackets in the tape."
+            ));
+        }
+        config.head = head;
+    }
+    info!("Tape model: {:?}", config.tape_model);
+    info!("Tape backend: {:?}", args.tape_backend);
+    // Transitions with weights make a machine probabilistic even without a seed.
+    let seed = (args.seed.is_some()
+        || args.trials.is_some()
+        || config.transitions.iter().any(|t| t.weight != 1))
+    .then(|| args.seed.unwrap_or_else(random_seed));
+    if let Some(seed) = seed {
+        info!("Seed: {}", seed);
+    } else if !args.is_nondeterministic {
+        warn_nondeterministic_transitions(&config);
+    }
+    let oracle = match (&config.oracle, &args.oracle) {
+        (Some(_), Some(oracle)) => {
+            info!("Oracle: {}", oracle);
+            Some(oracle_from_arg(
+                oracle,
+                args.oracle_steps,
+                args.tape_backend,
+            )?)
+        }
+        (Some(_), None) => {
+            return Err(anyhow!(
+                "The machine queries an oracle. Choose one with --oracle."
+            ))
+        }
+        (None, Some(_)) => {
+            return Err(anyhow!(
+                "--oracle needs an [oracle] section in the definition file."
+            ))
+        }
+        (None, None) => None,
+    };
+    let timeout = args.timeout.map(Duration::try_from_secs_f64).transpose()?;
+    let tape = args.tape.as_deref().unwrap_or_default();
+    if config.grid {
+        if args.resume.is_some() || args.checkpoint_every.is_some() {
+            return Err(anyhow!("Checkpoints are not supported in grid mode."));
+        }
+        if args.output.is_some() {
+            return Err(anyhow!("Output extraction is not supported in grid mode."));
+        }
+        if seed.is_some() {
+            return Err(anyhow!(
+                "Probabilistic machines are not supported in grid mode."
+            ));
+        }
+        if oracle.is_some() {
+            return Err(anyhow!("Oracles are not supported in grid mode."));
+        }
+        if args.is_nondeterministic {
+            return Err(anyhow!(
+                "Nondeterministic mode is not supported in grid mode."
+            ));
+        }
+        if args.detect_loop {
+            return Err(anyhow!("Loop detection is not supported in grid mode."));
+        }
+        if args.fast_forward {
+            return Err(anyhow!("Fast-forwarding is not supported in grid mode."));
+        }
+        if !args.breakpoints.is_empty() {
+            return Err(anyhow!("Breakpoints are not supported in grid mode."));
+        }
+        let mut tm = grid_turing_machine_from_config(&config, tape)?;
+        tm.run_with_limit(args.max_steps, timeout);
+        return print_grid_turing_machine(&tm, &args);
+    }
 
-    let separator = if args.show_separator { "|" } else { "" };
-    let tape_len = tm.tape.len();
-    let offset = tm.start_ptr;
-    let mut max_tape_symbol_lens = vec![1; tm.tape.len()];
-    for snapshot in &tm.snapshots {
-        for (i, s) in snapshot.tape.iter().enumerate() {
-            let i = i + (offset - snapshot.start_ptr);
-            max_tape_symbol_lens[i] = max_tape_symbol_lens
-                .get(i)
-                .map_or(s.len(), |len| *len.max(&s.len()));
+    let mut tm = turing_machine_from_config(&config, tape, args.tape_backend, oracle)?;
+    tm.seed = seed;
+    if args.detect_loop {
+        if seed.is_some() {
+            return Err(anyhow!(
+                "Loop detection is not supported for probabilistic machines."
+            ));
+        }
+        tm.enable_loop_detection();
+    }
+    if let Some(path) = &args.resume {
+        tm.restore(&Checkpoint::read_from_file(path)?)?;
+        // A run that stopped at its step or time limit can go on with new limits.
+        if tm.status == Status::Timeout {
+            tm.status = Status::Running;
+        }
+        info!("Resumed from \"{}\" at step {}", path, tm.steps);
+    }
+    tm.max_steps = args.max_steps;
+    tm.timeout = timeout;
+    if let (Some(trials), Some(seed)) = (args.trials, seed) {
+        if trials == 0 {
+            return Err(anyhow!("At least one trial is needed."));
+        }
+        let result = tm.monte_carlo(trials, seed);
+        println!("Trials: {}", result.trials);
+        for (status, count) in &result.statuses {
+            println!("{:>7}: {}", status, count);
+        }
+        let (low, high) = result.confidence_interval(1.96);
+        println!(
+            "Accept probability: {:.4} (95% confidence interval: {:.4} - {:.4})",
+            result.accept_probability(),
+            low,
+            high
+        );
+        return Ok(());
+    }
+    if args.fast_forward {
+        if args.is_nondeterministic || args.checkpoint_every.is_some() {
+            return Err(anyhow!(
+                "Fast-forward cannot be combined with nondeterministic mode or checkpoints."
+            ));
+        }
+        tm.fast_forward(args.macro_cells)?;
+    } else if args.is_nondeterministic {
+        tm.run_nondeterministic(args.max_frontier);
+    } else {
+        match args.checkpoint_every {
+            Some(every) => run_with_checkpoints(&mut tm, every.get(), &args.checkpoint_file)?,
+            None => {
+                tm.run();
+            }
+        }
+        if let Status::Loop { start, period } = tm.status {
+            info!("Loop detected: cycle starts at step {start} with period {period}");
+        }
+    }
+
+    if let Some(mode) = args.output {
+        if args.output_tape >= tm.tape_count() {
+            return Err(anyhow!("The machine has only {} tape(s).", tm.tape_count()));
         }
+        if !matches!(tm.status, Status::Accept | Status::Halt) {
+            warn!("The machine stopped with status {}.", tm.status);
+        }
+        println!("{}", tm.output(args.output_tape, mode));
+        return Ok(());
     }
-    let max_tape_symbol_len = *max_tape_symbol_lens.iter().max().unwrap_or(&1);
-    let get_max_tape_symbol_len = |i: usize| {
-        if args.is_monospace {
-            max_tape_symbol_len
+
+    let program = &tm.program;
+    let separator = if args.show_separator { "|" } else { "" };
+    let layouts = (0..tm.tape_count())
+        .map(|i| TapeLayout::new(&tm, i, args.is_monospace))
+        .collect::<Vec<_>>();
+    let render_tapes = |tapes: &[Box<dyn Tape>]| {
+        let head_columns = layouts
+            .iter()
+            .zip(tapes)
+            .map(|(layout, tape)| layout.head_column(tape.as_ref(), separator))
+            .collect::<Vec<_>>();
+        let max_head_column = head_columns.iter().copied().max().unwrap_or(0);
+        let lines = layouts
+            .iter()
+            .zip(tapes)
+            .zip(head_columns)
+            .map(|((layout, tape), head_column)| {
+                format!(
+                    "{}[{}]",
+                    " ".repeat(max_head_column - head_column),
+                    layout.build_tape_string(tape.as_ref(), separator)
+                )
+            })
+            .collect::<Vec<_>>();
+
+        (lines, max_head_column)
+    };
+
+    let print_step = |tapes: &[Box<dyn Tape>], step: &Step| {
+        let (tapes, head_column) = render_tapes(tapes);
+        let transition = format!(
+            "({}, {}) -> ({}, {})",
+            program.state(step.current_state).name,
+            program.alphabet().names(&step.read).join(", "),
+            program.state(step.next_state).name,
+            program.alphabet().names(&step.write).join(", "),
+        );
+
+        if args.is_snail_mode {
+            for tape in &tapes {
+                println!("{}", tape);
+            }
+            println!(" {}🐌<[{}]", " ".repeat(head_column), transition);
         } else {
-            max_tape_symbol_lens[i]
+            println!("{:>7}: {}: {}", step.status, tapes[0], transition);
+            for tape in &tapes[1..] {
+                println!("{:>7}  {}", "", tape);
+            }
         }
     };
-    let blank = if args.is_monospace {
-        tm.blank.repeat(max_tape_symbol_len)
+
+    if args.is_step_by_step || !args.breakpoints.is_empty() {
+        // Walks a copy of the finished machine through its history, so Backspace can go back.
+        let term = Term::stdout();
+        let mut cursor = tm.clone();
+        cursor.seek(cursor.history.first_step())?;
+        let mut stepped_back = false;
+        while let Some(step) = cursor.history.step(cursor.steps) {
+            print_step(&cursor.tapes, step);
+
+            // With breakpoints, only the steps that hit one wait for a key, along with the steps
+            // gone back to.
+            let is_breakpoint = || {
+                let mut tapes = cursor.tapes.clone();
+                step.apply(&mut tapes);
+                let event = StepEvent {
+                    number: cursor.steps,
+                    step,
+                    tapes: &tapes,
+                    program,
+                };
+                args.breakpoints.iter().any(|b| b.matches(&event))
+            };
+            if !args.breakpoints.is_empty() && !stepped_back && !is_breakpoint() {
+                cursor.seek(cursor.steps + 1)?;
+                continue;
+            }
+
+            let key = loop {
+                term.clear_line()?;
+                print!("Press Enter to continue, Backspace to go back...");
+                stdout().flush()?;
+                let key = term.read_key()?;
+                if matches!(key, console::Key::Enter | console::Key::Backspace) {
+                    break key;
+                }
+            };
+            term.clear_line()?;
+            stepped_back = key == console::Key::Backspace;
+            if stepped_back {
+                cursor.step_back();
+            } else {
+                cursor.seek(cursor.steps + 1)?;
+            }
+        }
     } else {
-        tm.blank.to_string()
-    };
+        for (snapshot, step) in tm.history.iter().zip(tm.history.steps()) {
+            print_step(&snapshot.tapes, step);
+        }
+    }
+
+    let (tapes, head_column) = render_tapes(&tm.tapes);
+    let status = tm.status.to_string();
+    if args.is_snail_mode {
+        for tape in &tapes {
+            println!("{}", tape);
+        }
+        println!(" {}🐌<[{}]", " ".repeat(head_column), status)
+    } else {
+        println!("{:>7}: {}", status, tapes[0]);
+        for tape in &tapes[1..] {
+            println!("{:>7}  {}", "", tape);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_with_checkpoints(tm: &mut TuringMachine, every: usize, path: &str) -> Result<()> {
+    while tm.next().is_some() {
+        if tm.steps.is_multiple_of(every) {
+            tm.checkpoint().write_to_file(path)?;
+        }
+    }
+    tm.checkpoint().write_to_file(path)?;
+    info!("Checkpoint saved to \"{}\" at step {}", path, tm.steps);
+
+    Ok(())
+}
+
+fn print_grid_turing_machine(tm: &GridTuringMachine, args: &Args) -> Result<()> {
+    let program = &tm.program;
+    let alphabet = program.alphabet();
+    let separator = if args.show_separator { "|" } else { "" };
+    let cell_len = tm
+        .snapshots
+        .iter()
+        .map(|s| &s.grid)
+        .chain([&tm.grid])
+        .flat_map(|g| g.cells.iter().flatten())
+        .map(|&s| alphabet.name(s).chars().count())
+        .max()
+        .unwrap_or(1);
+    let blank = program.symbol_name(tm.blank()).repeat(cell_len);
 
     let term = Term::stdout();
     for snapshot in &tm.snapshots {
-        let tape = (&snapshot.tape).clone();
-        let tape_ptr = snapshot.tape_ptr;
-        let start_ptr = snapshot.start_ptr;
-        let tape = build_tape_string(
-            tape,
-            tape_ptr,
-            offset - start_ptr,
+        println!(
+            "{:>7}: ({}, {}) -> ({}, {})",
+            snapshot.status,
+            program.state(snapshot.current_state).name,
+            alphabet.name(snapshot.read),
+            program.state(snapshot.next_state).name,
+            alphabet.name(snapshot.write),
+        );
+        for row in build_grid_string(
+            &snapshot.grid,
+            &tm.grid,
+            alphabet,
             &blank,
             separator,
-            tape_len,
-            get_max_tape_symbol_len,
-        );
-
-        if args.is_snail_mode {
-            let ptr = tape_ptr + offset - start_ptr;
-            let blanks = (0..ptr)
-                .map(|i| {
-                    " ".repeat(get_max_tape_symbol_len(i) + if args.show_separator { 1 } else { 0 })
-                })
-                .collect::<String>();
-            println!("[{}]", tape);
-            println!(
-                " {blanks}🐌<[({}, {}) -> ({}, {})]",
-                snapshot.current_state, snapshot.read, snapshot.next_state, snapshot.write,
-            );
-        } else {
-            println!(
-                "{:>7}: [{}]: ({}, {}) -> ({}, {})",
-                snapshot.status,
-                tape,
-                snapshot.current_state,
-                snapshot.read,
-                snapshot.next_state,
-                snapshot.write,
-            );
+            cell_len,
+        ) {
+            println!("[{}]", row);
         }
 
         if args.is_step_by_step {
@@ -133,78 +501,223 @@ fn main() -> Result<()> {
         }
     }
 
-    let tape = tm.tape;
-    let tape_ptr = tm.tape_ptr;
-    let start_ptr = tm.start_ptr;
-    let tape = build_tape_string(
-        tape,
-        tape_ptr,
-        offset - start_ptr,
-        &blank,
-        separator,
-        tape_len,
-        get_max_tape_symbol_len,
-    );
-    let status = tm.status.to_string();
-    if args.is_snail_mode {
-        let ptr = tape_ptr + offset - start_ptr;
-        let blanks = (0..ptr)
-            .map(|i| {
-                " ".repeat(get_max_tape_symbol_len(i) + if args.show_separator { 1 } else { 0 })
-            })
-            .collect::<String>();
-        println!("[{}]", tape);
-        println!(" {blanks}🐌<[{}]", status)
-    } else {
-        println!("{:>7}: [{}]", status, tape);
+    println!("{:>7}", tm.status);
+    for row in build_grid_string(&tm.grid, &tm.grid, alphabet, &blank, separator, cell_len) {
+        println!("[{}]", row);
     }
 
     Ok(())
 }
 
-fn turing_machine_from_config(config: &Config, tape: String) -> Result<TuringMachine> {
+fn turing_machine_from_config(
+    config: &Config,
+    tape: &str,
+    tape_backend: TapeBackend,
+    oracle: Option<BoxedOracle>,
+) -> Result<TuringMachine> {
+    if let Some(transition) = config
+        .transitions
+        .iter()
+        .find(|t| t.direction.to_vec().iter().any(|d| !d.is_linear()))
+    {
+        return Err(anyhow!(
+            "Invalid transition from \"{}\" to \"{}\". Up, down and turning directions need `grid = true`.",
+            transition.from,
+            transition.to
+        ));
+    }
+
+    let mut builder = builder_from_config(config, tape)?.set_tape_backend(tape_backend);
+    if config.tape_model == TapeModel::LinearBounded {
+        let (left_marker, right_marker) = &config.end_markers;
+        builder = builder.set_end_markers(left_marker, right_marker);
+    }
+    if let (Some(oracle), Some(states)) = (oracle, &config.oracle) {
+        builder = builder
+            .set_oracle(oracle, &states.query, &states.yes, &states.no)?
+            .set_query_tape(states.tape, states.region);
+    }
+
+    builder.build()
+}
+
+type BoxedOracle = Box<dyn Fn(&[&str]) -> bool + Send + Sync>;
+
+// Queries are matched and looked up with the names of their symbols joined together.
+fn oracle_from_arg(arg: &str, max_steps: usize, tape_backend: TapeBackend) -> Result<BoxedOracle> {
+    let Some((kind, value)) = arg.split_once(':') else {
+        return Err(anyhow!(
+            "Invalid oracle \"{}\". Use regex:PATTERN, machine:FILE or table:FILE.",
+            arg
+        ));
+    };
+    match kind {
+        "regex" => {
+            let regex = Regex::new(&format!("^(?:{value})$"))?;
+            Ok(Box::new(move |query: &[&str]| {
+                regex.is_match(&query.concat())
+            }))
+        }
+        "machine" => {
+            let config = Config::read_from_file(value)?;
+            if config.grid || config.oracle.is_some() {
+                return Err(anyhow!(
+                    "A machine oracle can neither use a grid nor query an oracle itself."
+                ));
+            }
+            Ok(Box::new(move |query: &[&str]| {
+                // Queries with symbols the machine doesn't know are answered no.
+                turing_machine_from_config(&config, &query.concat(), tape_backend, None)
+                    .map(|mut tm| {
+                        tm.max_steps = Some(max_steps);
+                        tm.record_history = false;
+                        tm.run() == Status::Accept
+                    })
+                    .unwrap_or(false)
+            }))
+        }
+        "table" => {
+            let content = fs::read_to_string(value)
+                .with_context(|| format!("Failed to read \"{}\"", value))?;
+            let table: HashMap<String, bool> =
+                toml::from_str(&content).context("Invalid oracle table")?;
+            Ok(Box::new(move |query: &[&str]| {
+                table.get(&query.concat()).copied().unwrap_or(false)
+            }))
+        }
+        _ => Err(anyhow!(
+            "Unknown oracle kind \"{}\". Use regex, machine or table.",
+            kind
+        )),
+    }
+}
+
+fn grid_turing_machine_from_config(config: &Config, tape: &str) -> Result<GridTuringMachine> {
+    if config.tapes != 1 {
+        return Err(anyhow!("Grid mode supports only a single tape."));
+    }
+    if config.tape_model != TapeModel::TwoWay {
+        return Err(anyhow!("Grid mode supports only the two-way tape model."));
+    }
+
+    builder_from_config(config, tape)?.build_grid()
+}
+
+fn builder_from_config<'a>(config: &'a Config, tape: &str) -> Result<TuringMachineBuilder<'a>> {
     let mut state_names = (config.transitions)
         .iter()
-        .map(|t| [t.from.clone(), t.to.clone()])
-        .flatten()
+        .flat_map(|t| [t.from.as_str(), t.to.as_str()])
         .collect::<Vec<_>>();
+    // The answer states may only ever be entered by the oracle.
+    if let Some(oracle) = &config.oracle {
+        state_names.extend([&oracle.query, &oracle.yes, &oracle.no].map(String::as_str));
+    }
     state_names.sort();
     state_names.dedup();
-    let states = state_names
-        .iter()
-        .map(|name| {
-            (
-                name.as_str(),
-                Rc::new(RefCell::new(State::new(name, vec![]))),
-            )
-        })
-        .collect::<HashMap<_, _>>();
+    let mut builder = TuringMachineBuilder::new().add_states(&state_names);
 
     for transition in &config.transitions {
-        let from = states.get(&transition.from.as_str()).unwrap();
-        let to = states.get(&transition.to.as_str()).unwrap();
-        from.borrow_mut().add_transition(Transition::new(
-            to,
-            transition.read.as_str(),
-            transition.write.as_str(),
-            transition.direction,
-        ));
+        let read = transition.read.to_vec();
+        let write = transition.write.to_vec();
+        let direction = transition.direction.to_vec();
+        if [read.len(), write.len(), direction.len()] != [config.tapes; 3] {
+            return Err(anyhow!(
+                "Invalid transition from \"{}\" to \"{}\". Read, write and direction must have {} element(s) each.",
+                transition.from,
+                transition.to,
+                config.tapes
+            ));
+        }
+
+        builder = builder.add_weighted_multi_tape_transition(
+            &transition.from,
+            &transition.to,
+            &read.iter().map(String::as_str).collect::<Vec<_>>(),
+            &write.iter().map(String::as_str).collect::<Vec<_>>(),
+            &direction,
+            transition.weight,
+        )?;
     }
 
-    let initial_state = states.get(&config.initial_state.as_str()).unwrap();
-    let accept_states = (&config.accept_states)
-        .into_iter()
-        .map(|name| states.get(name.as_str()))
+    let initial_state = find_states(
+        &state_names,
+        slice::from_ref(&config.initial_state),
+        "initial",
+    )?;
+    let accept_states = find_states(&state_names, &config.accept_states, "accept")?;
+    let reject_states = find_states(&state_names, &config.reject_states, "reject")?;
+    let halt_states = find_states(&state_names, &config.halt_states, "halt")?;
+
+    let alphabet = tape_alphabet(config);
+    let (tape, head) = parse_tape(
+        tape,
+        alphabet.iter().map(|s| s.as_str()).collect::<Vec<&str>>(),
+    )?;
+
+    let builder = builder
+        .set_initial_state(initial_state[0])?
+        .set_accept_states(&accept_states)?
+        .set_reject_states(&reject_states)?
+        .set_halt_states(&halt_states)?
+        .set_tape(tape)
+        .set_head(head.unwrap_or(config.head))
+        .set_tape_count(config.tapes)
+        .set_tape_model(config.tape_model)
+        .set_blank_symbol(&config.blank);
+
+    Ok(builder)
+}
+
+// The names of the symbols the input may be written with.
+fn tape_alphabet(config: &Config) -> Vec<String> {
+    let mut alphabet = config
+        .transitions
+        .iter()
+        .flat_map(|t| [t.read.to_vec(), t.write.to_vec()].concat())
+        .filter(|s| s != Symbol::ANY_NAME)
+        .chain([config.blank.clone()])
         .collect::<Vec<_>>();
-    if accept_states.iter().any(|s| s.is_none()) {
+    alphabet.sort();
+    alphabet.dedup();
+
+    alphabet
+}
+
+fn random_seed() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    now.as_nanos() as u64
+}
+
+fn warn_nondeterministic_transitions(config: &Config) {
+    let mut seen = HashSet::new();
+    for transition in &config.transitions {
+        let read = transition.read.to_vec();
+        if !seen.insert((&transition.from, read.clone())) {
+            warn!(
+                "State \"{}\" has several transitions reading \"{}\". Only the first one is used unless --nondeterministic or --seed is set.",
+                transition.from,
+                read.join(", ")
+            );
+        }
+    }
+}
+
+fn find_states<'a>(state_names: &[&str], names: &'a [String], kind: &str) -> Result<Vec<&'a str>> {
+    if names
+        .iter()
+        .any(|name| !state_names.contains(&name.as_str()))
+    {
         return Err(anyhow!(
-            "Invalid accept state: {}. Accept state must be one of {}.",
-            config
-                .accept_states
+            "Invalid {kind} state: {}. {} state must be one of {}.",
+            names
                 .iter()
                 .map(|s| format!("\"{}\"", s))
                 .collect::<Vec<String>>()
                 .join(", "),
+            kind[..1].to_uppercase() + &kind[1..],
             state_names
                 .iter()
                 .map(|s| format!("\"{}\"", s))
@@ -212,73 +725,194 @@ fn turing_machine_from_config(config: &Config, tape: String) -> Result<TuringMac
                 .join(", ")
         ));
     }
-    let accept_states = accept_states
-        .into_iter()
-        .map(|s| s.unwrap().clone())
-        .collect::<Vec<_>>();
 
-    let mut alphabet = config
-        .transitions
-        .iter()
-        .map(|t| [t.read.clone(), t.write.clone()])
-        .flatten()
-        .collect::<Vec<_>>();
-    alphabet.sort();
-    alphabet.dedup();
+    Ok(names.iter().map(String::as_str).collect())
+}
 
-    let tm = TuringMachine::new(
-        &initial_state,
-        accept_states.as_slice(),
-        &string_to_tape(
-            &tape,
-            alphabet.iter().map(|s| s.as_str()).collect::<Vec<&str>>(),
-        )?,
-        config.blank.as_str(),
-    );
+struct TapeLayout<'a> {
+    alphabet: &'a Alphabet,
+    start: isize,
+    tape_len: usize,
+    max_tape_symbol_lens: Vec<usize>,
+    max_tape_symbol_len: usize,
+    blank: String,
+    is_monospace: bool,
+}
+impl<'a> TapeLayout<'a> {
+    fn new(tm: &'a TuringMachine, index: usize, is_monospace: bool) -> Self {
+        let alphabet = tm.program.alphabet();
+        let tape = &tm.tapes[index];
+        let start = tape.bounds().start;
+        let tape_len = tape.bounds().len();
+        let mut max_tape_symbol_lens = vec![1; tape_len];
+        // Every symbol a cell has ever held is either on the initial tape or written by a step.
+        let initial_tape = &tm.history.initial_tapes()[index];
+        let initial_cells = initial_tape.bounds().map(|p| (p, initial_tape.get(p)));
+        let writes = (tm.history.steps())
+            .iter()
+            .map(|step| (step.head_moves[index].0, step.write[index]));
+        for (position, s) in initial_cells.chain(writes) {
+            let i = (position - start) as usize;
+            max_tape_symbol_lens[i] = max_tape_symbol_lens[i].max(alphabet.name(s).len());
+        }
+        let max_tape_symbol_len = *max_tape_symbol_lens.iter().max().unwrap_or(&1);
+        let blank = alphabet.name(tm.blank());
+        let blank = if is_monospace {
+            blank.repeat(max_tape_symbol_len)
+        } else {
+            blank.to_string()
+        };
+
+        Self {
+            alphabet,
+            start,
+            tape_len,
+            max_tape_symbol_lens,
+            max_tape_symbol_len,
+            blank,
+            is_monospace,
+        }
+    }
 
-    Ok(tm)
+    fn get_max_tape_symbol_len(&self, i: usize) -> usize {
+        if self.is_monospace {
+            self.max_tape_symbol_len
+        } else {
+            self.max_tape_symbol_lens[i]
+        }
+    }
+
+    fn head_column(&self, tape: &dyn Tape, separator: &str) -> usize {
+        let ptr = (tape.head() - self.start) as usize;
+        (0..ptr)
+            .map(|i| self.get_max_tape_symbol_len(i) + separator.len())
+            .sum()
+    }
+
+    fn build_tape_string(&self, tape: &dyn Tape, separator: &str) -> String {
+        build_tape_string(
+            tape,
+            self.alphabet,
+            (tape.bounds().start - self.start) as usize,
+            &self.blank,
+            separator,
+            self.tape_len,
+            |i| self.get_max_tape_symbol_len(i),
+        )
+    }
 }
 
 fn build_tape_string(
-    tape: Vec<String>,
-    tape_ptr: usize,
+    tape: &dyn Tape,
+    alphabet: &Alphabet,
     offset: usize,
     blank: &str,
     separator: &str,
     tape_len: usize,
     get_max_tape_symbol_len: impl Fn(usize) -> usize,
 ) -> String {
-    let tape = tape
-        .into_iter()
+    let symbols = tape
+        .symbols()
+        .iter()
         .enumerate()
-        .map(|(i, s)| {
+        .map(|(i, &s)| {
+            let s = alphabet.name(s);
             let i = i + offset;
             let len = get_max_tape_symbol_len(i);
-            let s = if s[..1] == blank[..1] {
+            let s = if s.chars().next() == blank.chars().next() {
                 s.repeat(len)
             } else {
-                s
+                s.to_string()
             };
             format!("{:<len$}", s)
         })
         .collect::<Vec<_>>();
 
     let mut tmp = vec![blank.to_string(); offset];
-    tmp.extend(tape.clone());
+    tmp.extend(symbols);
     tmp.extend(vec![blank.to_string(); tape_len - tmp.len()]);
-    let mut tape = tmp;
+    let mut symbols = tmp;
+
+    let tape_ptr = (tape.head() - tape.bounds().start) as usize + offset;
+    let s = &symbols[tape_ptr];
+    symbols[tape_ptr] = s.reversed().green().to_string();
+    symbols.join(separator)
+}
+
+fn build_grid_string(
+    grid: &GridTape,
+    bounds: &GridTape,
+    alphabet: &Alphabet,
+    blank: &str,
+    separator: &str,
+    cell_len: usize,
+) -> Vec<String> {
+    let offset_x = bounds.origin.0 - grid.origin.0;
+    let offset_y = bounds.origin.1 - grid.origin.1;
+
+    (0..bounds.height())
+        .map(|y| {
+            (0..bounds.width())
+                .map(|x| {
+                    let cell = y
+                        .checked_sub(offset_y)
+                        .zip(x.checked_sub(offset_x))
+                        .and_then(|(y, x)| grid.cells.get(y).and_then(|row| row.get(x)))
+                        .map(|&s| alphabet.name(s));
+                    let s = match cell {
+                        Some(s) if s.chars().next() != blank.chars().next() => {
+                            format!("{:<cell_len$}", s)
+                        }
+                        _ => blank.to_string(),
+                    };
+                    if (x, y) == (grid.head.0 + offset_x, grid.head.1 + offset_y) {
+                        s.reversed().green().to_string()
+                    } else {
+                        s
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(separator)
+        })
+        .collect()
+}
+
+// The head can be marked by putting brackets around the symbol it starts on, as in `10[1]01`.
+// If a bracket is part of a symbol, brackets are read as symbols and only `--head` moves the head.
+fn parse_tape<'a>(
+    s: &str,
+    alphabet: impl Into<Vec<&'a str>>,
+) -> Result<(Vec<String>, Option<isize>)> {
+    let alphabet = alphabet.into();
+    let has_brackets = alphabet.iter().any(|name| name.contains(['[', ']']));
+    let Some((before, rest)) = s.split_once('[').filter(|_| !has_brackets) else {
+        return Ok((string_to_tape(s, alphabet)?, None));
+    };
+    let (head, after) = rest
+        .split_once(']')
+        .ok_or_else(|| anyhow!("Invalid tape: \"{}\". \"[\" must be closed by \"]\".", s))?;
 
-    let tape_ptr = tape_ptr + offset;
-    let s = &tape[tape_ptr];
-    tape[tape_ptr] = s.reversed().green().to_string();
-    let tape = tape.join(separator);
+    let mut tape = string_to_tape(before, alphabet.clone())?;
+    let position = tape.len() as isize;
+    let head = string_to_tape(head, alphabet.clone())?;
+    if head.len() != 1 {
+        return Err(anyhow!(
+            "Invalid tape: \"{}\". Exactly one symbol must be put in \"[]\".",
+            s
+        ));
+    }
+    tape.extend(head);
+    tape.extend(string_to_tape(after, alphabet)?);
 
-    tape
+    Ok((tape, Some(position)))
 }
 
 fn string_to_tape<'a>(s: &str, alphabet: impl Into<Vec<&'a str>>) -> Result<Vec<String>> {
     let alphabet = alphabet.into();
-    let mut tree = PatriciaNode::new(alphabet[0]);
+    let Some(first) = alphabet.first() else {
+        return Err(anyhow!("The tape alphabet is empty."));
+    };
+    let mut tree = PatriciaNode::new(first);
     for name in alphabet.iter().skip(1) {
         tree.insert(name).unwrap();
     }
@@ -333,16 +967,60 @@ mod tests {
         v.iter().map(|s| s.to_string()).collect()
     }
 
+    #[test]
+    fn test_oracle_from_arg() {
+        let oracle = oracle_from_arg("regex:(01)*", 0, TapeBackend::Deque).unwrap();
+        assert!(oracle(&["0", "1", "0", "1"]));
+        assert!(oracle(&[]));
+        assert!(!oracle(&["0", "1", "0"]));
+        assert!(!oracle(&["1", "0", "1"]));
+
+        assert!(oracle_from_arg("regex:(", 0, TapeBackend::Deque).is_err());
+        assert!(oracle_from_arg("coin:heads", 0, TapeBackend::Deque).is_err());
+        assert!(oracle_from_arg("table", 0, TapeBackend::Deque).is_err());
+    }
+
+    #[test]
+    fn test_build_grid_string() {
+        let mut alphabet = Alphabet::new();
+        let [blank, full] = ["□", "█"].map(|name| alphabet.intern(name));
+        let grid = GridTape::new(&[full, blank, full], blank);
+        let rows = build_grid_string(&grid, &grid, &alphabet, "□", "|", 1);
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].ends_with("|□|█"));
+    }
+
+    #[test]
+    fn test_parse_tape() {
+        let alphabet = ["0", "1", "_"];
+        let (tape, head) = parse_tape("10[1]01", alphabet).unwrap();
+        assert_eq!(tape, to_vec_string(vec!["1", "0", "1", "0", "1"]));
+        assert_eq!(head, Some(2));
+
+        let (tape, head) = parse_tape("[_]101", alphabet).unwrap();
+        assert_eq!(tape, to_vec_string(vec!["_", "1", "0", "1"]));
+        assert_eq!(head, Some(0));
+
+        assert_eq!(parse_tape("101", alphabet).unwrap().1, None);
+        assert!(parse_tape("1[01]", alphabet).is_err());
+        assert!(parse_tape("1[0", alphabet).is_err());
+
+        let alphabet = ["[", "]", "_"];
+        let (tape, head) = parse_tape("[[]]", alphabet).unwrap();
+        assert_eq!(tape, to_vec_string(vec!["[", "[", "]", "]"]));
+        assert_eq!(head, None);
+    }
+
     #[test]
     fn test_string_to_tape() {
         let alphabet = ["a", "b", "c", "d", "e"];
-        let tape = string_to_tape("abcde", &alphabet);
+        let tape = string_to_tape("abcde", alphabet);
 
         assert!(tape.is_ok());
         assert_eq!(tape.unwrap(), to_vec_string(vec!["a", "b", "c", "d", "e"]));
 
         let alphabet = ["a", "a'", "#a", "a''", "b", "c"];
-        let tape = string_to_tape("aaaabbbb#aa'a''", &alphabet);
+        let tape = string_to_tape("aaaabbbb#aa'a''", alphabet);
 
         assert!(tape.is_ok());
         assert_eq!(
@@ -353,8 +1031,10 @@ mod tests {
         );
 
         let alphabet = ["a", "a'", "#a", "a''", "b", "c"];
-        let tape = string_to_tape("ab#a'a''", &alphabet);
+        let tape = string_to_tape("ab#a'a''", alphabet);
 
         assert!(tape.is_err());
+
+        assert!(string_to_tape("", Vec::new()).is_err());
     }
 }
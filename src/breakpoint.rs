@@ -0,0 +1,144 @@
+use crate::{
+    observer::StepEvent,
+    turing_machine::{Status, TuringMachine},
+};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("Invalid breakpoint \"{0}\". Use state=NAME, symbol=NAME, step=N or tape=PATTERN")]
+pub struct InvalidBreakpoint(String);
+
+// A condition on a step that has just been taken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    // The machine enters the state.
+    State(String),
+    // The symbol is read from any tape.
+    Symbol(String),
+    // The machine has taken this many steps.
+    Step(usize),
+    // The first tape, trimmed of blanks at both ends, matches the pattern, in which `?` stands for
+    // any one symbol and `*` for any number of symbols.
+    Tape(String),
+}
+impl Breakpoint {
+    pub fn matches(&self, event: &StepEvent) -> bool {
+        let program = event.program;
+        match self {
+            Breakpoint::State(name) => program.state(event.step.next_state).name == *name,
+            Breakpoint::Symbol(name) => {
+                (event.step.read.iter()).any(|&symbol| program.symbol_name(symbol) == name)
+            }
+            Breakpoint::Step(step) => event.number + 1 == *step,
+            Breakpoint::Tape(pattern) => {
+                let tape = &event.tapes[0];
+                let symbols = program.alphabet().names(&tape.symbols());
+                let blank = program.symbol_name(tape.blank());
+                let start = symbols.iter().position(|&s| s != blank);
+                let end = symbols.iter().rposition(|&s| s != blank);
+                let content = match start.zip(end) {
+                    Some((start, end)) => &symbols[start..=end],
+                    None => &[],
+                };
+
+                glob_match(&pattern.chars().collect::<Vec<_>>(), content)
+            }
+        }
+    }
+}
+impl FromStr for Breakpoint {
+    type Err = InvalidBreakpoint;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidBreakpoint(s.to_string());
+        let (kind, value) = s.split_once('=').ok_or_else(invalid)?;
+        match kind {
+            "state" => Ok(Breakpoint::State(value.to_string())),
+            "symbol" => Ok(Breakpoint::Symbol(value.to_string())),
+            "step" => value.parse().map(Breakpoint::Step).map_err(|_| invalid()),
+            "tape" => Ok(Breakpoint::Tape(value.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// Symbols with longer names are matched by the characters of the pattern one symbol at a time,
+// so `ab` matches the symbol `ab` as well as the symbols `a` and `b`.
+fn glob_match(pattern: &[char], symbols: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => symbols.is_empty(),
+        Some(('*', rest)) => (0..=symbols.len()).any(|i| glob_match(rest, &symbols[i..])),
+        Some(('?', rest)) => !symbols.is_empty() && glob_match(rest, &symbols[1..]),
+        Some(_) => symbols.first().is_some_and(|symbol| {
+            let len = symbol.chars().count();
+            pattern.len() >= len
+                && pattern[..len].iter().copied().eq(symbol.chars())
+                && glob_match(&pattern[len..], &symbols[1..])
+        }),
+    }
+}
+
+impl TuringMachine {
+    // Runs the machine until a step satisfies `predicate` or the machine stops. The machine is
+    // still `Running` if it was paused.
+    pub fn run_until(&mut self, mut predicate: impl FnMut(&StepEvent) -> bool) -> Status {
+        let mut paused = false;
+        while !paused {
+            let mut observer = |event: &StepEvent| paused = predicate(event);
+            if self.next_with_observer(&mut observer).is_none() {
+                break;
+            }
+        }
+
+        self.status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Direction, TuringMachineBuilder};
+    use anyhow::Result;
+
+    #[test]
+    fn test_breakpoints() -> Result<()> {
+        // Turns 1111 into 0000 going right, and then into xxxx going left.
+        let tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C"])
+            .add_transition("A", "A", "1", "0", Direction::Right)?
+            .add_transition("A", "B", "_", "_", Direction::Left)?
+            .add_transition("B", "B", "0", "x", Direction::Left)?
+            .add_transition("B", "C", "_", "_", Direction::Right)?
+            .set_initial_state("A")?
+            .set_accept_states(&["C"])?
+            .set_tape(vec!["1".to_string(); 4])
+            .set_blank_symbol("_")
+            .build()?;
+        let paused_at = |breakpoint: &str| -> Result<Vec<usize>> {
+            let breakpoint = breakpoint.parse::<Breakpoint>()?;
+            let mut tm = tm.clone();
+            let mut paused_at = vec![];
+            while tm.run_until(|event| breakpoint.matches(event)) == Status::Running {
+                paused_at.push(tm.steps);
+            }
+            assert_eq!(tm.status, Status::Accept);
+
+            Ok(paused_at)
+        };
+
+        assert_eq!(paused_at("state=B")?, vec![5, 6, 7, 8, 9]);
+        assert_eq!(paused_at("symbol=_")?, vec![5, 10]);
+        assert_eq!(paused_at("step=7")?, vec![7]);
+        assert_eq!(paused_at("tape=x*")?, vec![9, 10]);
+        assert_eq!(paused_at("tape=0?0x")?, vec![6]);
+
+        assert!("step=x".parse::<Breakpoint>().is_err());
+        assert!("head=1".parse::<Breakpoint>().is_err());
+        assert!(glob_match(&['1', '?', '*'], &["1", "ab", "c"]));
+        assert!(glob_match(&['a', 'b'], &["ab"]));
+        assert!(!glob_match(&['a'], &["ab"]));
+
+        Ok(())
+    }
+}
@@ -1,149 +1,478 @@
-use crate::{Direction, State, Transition, TuringMachine};
+use crate::{
+    oracle::{Oracle, OracleLink},
+    program::Program,
+    state::{StateId, StateKind},
+    symbol::{Alphabet, Symbol},
+    Direction, GridTuringMachine, OutputMode, State, TapeBackend, TapeModel, Transition,
+    TuringMachine,
+};
 use anyhow::{anyhow, bail, Context, Result};
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum BuilderError {
     #[error("State {0} is not found")]
     StateNotFound(String),
+    #[error("State {0} is listed as more than one of accept, reject and halt states")]
+    StateKindConflict(String),
+    #[error(
+        "Transition from state {state} is for {actual} tapes, but the machine has {expected} tapes"
+    )]
+    TapeCountMismatch {
+        state: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Transition from state {0} moves the head in a direction that needs a grid tape")]
+    GridDirection(String),
+    #[error("Grid mode supports only a single two-way tape")]
+    GridTape,
+    #[error("Initial head position {0} is outside the tape")]
+    HeadOutOfBounds(isize),
+    #[error("Grid mode does not support an initial head position")]
+    GridHead,
+    #[error("Transition from state {0} has a weight of 0")]
+    ZeroWeight(String),
+    #[error("Loop detection does not support probabilistic machines")]
+    ProbabilisticLoop,
+    #[error("Query tape {0} is not one of the tapes of the machine")]
+    QueryTape(usize),
+    #[error("Grid mode does not support oracles")]
+    GridOracle,
 }
 
+#[derive(Clone)]
 pub struct TuringMachineBuilder<'a> {
-    states: HashMap<&'a str, Rc<RefCell<State>>>,
-    initial_state: Option<Rc<RefCell<State>>>,
-    accept_states: Vec<Rc<RefCell<State>>>,
+    states: Vec<State>,
+    state_ids: HashMap<&'a str, StateId>,
+    transitions: Vec<Transition>,
+    alphabet: Alphabet,
+    initial_state: Option<StateId>,
+    accept_states: Vec<StateId>,
+    reject_states: Vec<StateId>,
+    halt_states: Vec<StateId>,
     tape: Vec<String>,
+    head: isize,
+    tape_count: usize,
+    tape_model: TapeModel,
+    tape_backend: TapeBackend,
+    end_markers: Option<(&'a str, &'a str)>,
     blank_symbol: Option<&'a str>,
+    max_steps: Option<usize>,
+    timeout: Option<Duration>,
+    detect_loop: bool,
+    record_history: bool,
+    seed: Option<u64>,
+    oracle: Option<(Arc<dyn Oracle>, [StateId; 3])>,
+    query_tape: (usize, OutputMode),
 }
 impl<'a> TuringMachineBuilder<'a> {
     pub fn new() -> Self {
         Self {
-            states: HashMap::new(),
+            states: vec![],
+            state_ids: HashMap::new(),
+            transitions: vec![],
+            alphabet: Alphabet::new(),
             initial_state: None,
             accept_states: vec![],
+            reject_states: vec![],
+            halt_states: vec![],
             tape: vec![],
+            head: 0,
+            tape_count: 1,
+            tape_model: TapeModel::default(),
+            tape_backend: TapeBackend::default(),
+            end_markers: None,
             blank_symbol: None,
+            max_steps: None,
+            timeout: None,
+            detect_loop: false,
+            record_history: true,
+            seed: None,
+            oracle: None,
+            query_tape: (0, OutputMode::default()),
         }
     }
 
     pub fn add_state(mut self, name: &'a str) -> Self {
-        self.states
-            .insert(name, Rc::new(RefCell::new(State::new(name, vec![]))));
+        if !self.state_ids.contains_key(name) {
+            self.state_ids.insert(name, StateId(self.states.len()));
+            self.states.push(State::new(name));
+        }
 
         self
     }
 
-    pub fn add_states(mut self, names: &[&'a str]) -> Self {
-        self.states.extend(
-            names
-                .iter()
-                .map(|&name| (name, Rc::new(RefCell::new(State::new(name, vec![])))))
-                .collect::<HashMap<_, _>>(),
-        );
-
-        self
+    pub fn add_states(self, names: &[&'a str]) -> Self {
+        names
+            .iter()
+            .fold(self, |builder, name| builder.add_state(name))
     }
 
     pub fn add_transition(
         self,
         from: &'a str,
         to: &'a str,
-        read: &'a str,
-        write: &'a str,
+        read: &str,
+        write: &str,
         direction: Direction,
     ) -> Result<Self> {
-        let from = self
-            .states
-            .get(from)
-            .ok_or(BuilderError::StateNotFound(from.to_string()))?;
-        let to = self
-            .states
-            .get(to)
-            .ok_or(BuilderError::StateNotFound(to.to_string()))?;
-        from.borrow_mut()
-            .add_transition(Transition::new(to, read, write, direction));
+        self.add_multi_tape_transition(from, to, &[read], &[write], &[direction])
+    }
+
+    pub fn add_transitions(
+        mut self,
+        transitions: &[(&'a str, &'a str, &'a str, &'a str, Direction)],
+    ) -> Result<Self> {
+        for &(from, to, read, write, direction) in transitions {
+            self = self.add_transition(from, to, read, write, direction)?;
+        }
 
         Ok(self)
     }
 
-    pub fn add_transitions(
+    pub fn add_multi_tape_transition(
         self,
-        transitions: &[(&'a str, &'a str, &'a str, &'a str, Direction)],
+        from: &'a str,
+        to: &'a str,
+        read: &[&str],
+        write: &[&str],
+        directions: &[Direction],
     ) -> Result<Self> {
-        for (from, to, read, write, direction) in transitions {
-            let from = self
-                .states
-                .get(from)
-                .ok_or(BuilderError::StateNotFound(from.to_string()))?;
-            let to = self
-                .states
-                .get(to)
-                .ok_or(BuilderError::StateNotFound(to.to_string()))?;
-            let transition = Transition::new(to, read, write, direction.clone());
+        self.add_weighted_multi_tape_transition(from, to, read, write, directions, 1)
+    }
 
-            from.borrow_mut().add_transition(transition);
-        }
+    // A probabilistic run chooses among the transitions for the same state and symbols with
+    // probabilities proportional to their weights. Other runs ignore the weights.
+    pub fn add_weighted_transition(
+        self,
+        from: &'a str,
+        to: &'a str,
+        read: &str,
+        write: &str,
+        direction: Direction,
+        weight: u32,
+    ) -> Result<Self> {
+        self.add_weighted_multi_tape_transition(from, to, &[read], &[write], &[direction], weight)
+    }
+
+    pub fn add_weighted_multi_tape_transition(
+        mut self,
+        from: &'a str,
+        to: &'a str,
+        read: &[&str],
+        write: &[&str],
+        directions: &[Direction],
+        weight: u32,
+    ) -> Result<Self> {
+        let from = self.get_state(from)?;
+        let to = self.get_state(to)?;
+        let read = self.intern_symbols(read);
+        let write = self.intern_symbols(write);
+        self.transitions.push(
+            Transition::new_multi_tape(from, to, &read, &write, directions).with_weight(weight),
+        );
 
         Ok(self)
     }
 
     pub fn set_initial_state(mut self, name: &'a str) -> Result<Self> {
-        self.initial_state = match self.states.get(name) {
-            Some(state) => Some(state.clone()),
-            None => bail!(BuilderError::StateNotFound(name.to_string())),
-        };
+        self.initial_state = Some(self.get_state(name)?);
 
         Ok(self)
     }
 
     pub fn set_accept_states(mut self, names: &[&'a str]) -> Result<Self> {
-        self.accept_states = names
-            .iter()
-            .map(|&name| {
-                self.states
-                    .get(name)
-                    .map(|state| state.clone())
-                    .ok_or(anyhow!(BuilderError::StateNotFound(name.to_string())))
-            })
-            .collect::<Result<Vec<_>>>()?;
+        self.accept_states = self.get_states(names)?;
+
+        Ok(self)
+    }
+
+    pub fn set_reject_states(mut self, names: &[&'a str]) -> Result<Self> {
+        self.reject_states = self.get_states(names)?;
+
+        Ok(self)
+    }
+
+    pub fn set_halt_states(mut self, names: &[&'a str]) -> Result<Self> {
+        self.halt_states = self.get_states(names)?;
 
         Ok(self)
     }
 
+    fn get_state(&self, name: &str) -> Result<StateId> {
+        self.state_ids
+            .get(name)
+            .copied()
+            .ok_or(anyhow!(BuilderError::StateNotFound(name.to_string())))
+    }
+
+    fn get_states(&self, names: &[&'a str]) -> Result<Vec<StateId>> {
+        names
+            .iter()
+            .map(|&name| self.get_state(name))
+            .collect::<Result<Vec<_>>>()
+    }
+
+    // `*` is a wildcard rather than a tape symbol, so it is never interned.
+    fn intern_symbols(&mut self, names: &[impl AsRef<str>]) -> Vec<Symbol> {
+        names
+            .iter()
+            .map(|name| match name.as_ref() {
+                Symbol::ANY_NAME => Symbol::ANY,
+                name => self.alphabet.intern(name),
+            })
+            .collect()
+    }
+
     pub fn set_tape(mut self, tape: Vec<String>) -> Self {
         self.tape = tape;
 
         self
     }
 
+    // The position the head of the first tape starts at, relative to the first input cell.
+    pub fn set_head(mut self, position: isize) -> Self {
+        self.head = position;
+
+        self
+    }
+
+    pub fn set_tape_count(mut self, tape_count: usize) -> Self {
+        self.tape_count = tape_count;
+
+        self
+    }
+
+    pub fn set_tape_model(mut self, tape_model: TapeModel) -> Self {
+        self.tape_model = tape_model;
+
+        self
+    }
+
+    pub fn set_tape_backend(mut self, tape_backend: TapeBackend) -> Self {
+        self.tape_backend = tape_backend;
+
+        self
+    }
+
+    // A linear bounded tape gets `<` and `>` unless other markers are set.
+    pub fn set_end_markers(mut self, left_marker: &'a str, right_marker: &'a str) -> Self {
+        self.end_markers = Some((left_marker, right_marker));
+
+        self
+    }
+
     pub fn set_blank_symbol(mut self, blank_symbol: &'a str) -> Self {
         self.blank_symbol = Some(blank_symbol);
 
         self
     }
 
-    pub fn build(self) -> Result<TuringMachine> {
+    pub fn set_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = Some(max_steps);
+
+        self
+    }
+
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+
+        self
+    }
+
+    pub fn enable_loop_detection(mut self) -> Self {
+        self.detect_loop = true;
+
+        self
+    }
+
+    // Keeps the machine from recording its steps, for runs too long to keep every step of.
+    pub fn disable_history(mut self) -> Self {
+        self.record_history = false;
+
+        self
+    }
+
+    // Makes the machine probabilistic. Runs with the same seed take the same transitions.
+    pub fn set_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+
+        self
+    }
+
+    // Entering `query` asks `oracle` about the query on the query tape, and moves on to `yes` or
+    // `no` depending on the answer.
+    pub fn set_oracle(
+        mut self,
+        oracle: impl Oracle + 'static,
+        query: &'a str,
+        yes: &'a str,
+        no: &'a str,
+    ) -> Result<Self> {
+        let states = [
+            self.get_state(query)?,
+            self.get_state(yes)?,
+            self.get_state(no)?,
+        ];
+        self.oracle = Some((Arc::new(oracle), states));
+
+        Ok(self)
+    }
+
+    // The tape the query is read from, and the part of it that makes up the query. Defaults to
+    // the whole first tape.
+    pub fn set_query_tape(mut self, tape: usize, region: OutputMode) -> Self {
+        self.query_tape = (tape, region);
+
+        self
+    }
+
+    pub fn build(mut self) -> Result<TuringMachine> {
+        // A probabilistic machine can leave a configuration differently the next time round.
+        if self.detect_loop && self.seed.is_some() {
+            bail!(BuilderError::ProbabilisticLoop);
+        }
+        if self.tape_model == TapeModel::LinearBounded {
+            self.end_markers.get_or_insert(("<", ">"));
+        }
+        let (program, tape) = self.build_program(false)?;
+        let end_markers = self
+            .end_markers
+            .and_then(|(left, right)| program.symbol(left).zip(program.symbol(right)));
+
+        let mut tm = TuringMachine::with_tape_backend(Arc::new(program), &tape, self.tape_backend);
+        tm.set_tape_model(self.tape_model);
+        if let Some((left_marker, right_marker)) = end_markers {
+            tm.add_end_markers(left_marker, right_marker);
+        }
+        if self.head != 0 {
+            // Only a two-way tape can grow to the left, and a linear bounded tape can't grow.
+            let is_on_tape = match self.tape_model {
+                TapeModel::TwoWay => true,
+                TapeModel::OneWay | TapeModel::OneWayStrict => self.head >= 0,
+                TapeModel::LinearBounded => tm.tapes[0].bounds().contains(&self.head),
+            };
+            if !is_on_tape {
+                bail!(BuilderError::HeadOutOfBounds(self.head));
+            }
+            tm.set_head(self.head);
+        }
+        tm.max_steps = self.max_steps;
+        tm.timeout = self.timeout;
+        tm.record_history = self.record_history;
+        tm.seed = self.seed;
+        if let Some((oracle, [query_state, yes_state, no_state])) = self.oracle {
+            let (tape, region) = self.query_tape;
+            if tape >= tm.tape_count() {
+                bail!(BuilderError::QueryTape(tape));
+            }
+            tm.oracle = Some(OracleLink {
+                oracle,
+                query_state,
+                yes_state,
+                no_state,
+                tape,
+                region,
+            });
+        }
+        if self.detect_loop {
+            tm.enable_loop_detection();
+        }
+
+        Ok(tm)
+    }
+
+    pub fn build_grid(self) -> Result<GridTuringMachine> {
+        if self.tape_count != 1 || self.tape_model != TapeModel::TwoWay {
+            bail!(BuilderError::GridTape);
+        }
+        if self.head != 0 {
+            bail!(BuilderError::GridHead);
+        }
+        if self.oracle.is_some() {
+            bail!(BuilderError::GridOracle);
+        }
+        let (program, tape) = self.build_program(true)?;
+
+        let mut tm = GridTuringMachine::new(Arc::new(program), &tape);
+        tm.max_steps = self.max_steps;
+        tm.timeout = self.timeout;
+
+        Ok(tm)
+    }
+
+    // Returns the program together with the initial tape, interned into the program's alphabet.
+    fn build_program(&self, is_grid: bool) -> Result<(Program, Vec<Symbol>)> {
         let initial_state = self.initial_state.context("Initial state is not set")?;
-        let accept_states = self.accept_states;
-        let tape = self.tape;
         let blank_symbol = self.blank_symbol.context("Blank symbol is not set")?;
+        for transition in &self.transitions {
+            let state = &self.states[transition.from.0];
+            if !is_grid && !transition.is_linear() {
+                bail!(BuilderError::GridDirection(state.name.clone()));
+            }
+            if transition.weight == 0 {
+                bail!(BuilderError::ZeroWeight(state.name.clone()));
+            }
+            if transition.tape_count() != Some(self.tape_count) {
+                bail!(BuilderError::TapeCountMismatch {
+                    state: state.name.clone(),
+                    expected: self.tape_count,
+                    actual: transition.tape_count().unwrap_or(0),
+                });
+            }
+        }
+
+        let mut states = self.states.clone();
+        for (ids, kind) in [
+            (&self.accept_states, StateKind::Accept),
+            (&self.halt_states, StateKind::Halt),
+            (&self.reject_states, StateKind::Reject),
+        ] {
+            for id in ids {
+                let state = &mut states[id.0];
+                if state.kind != StateKind::Normal && state.kind != kind {
+                    bail!(BuilderError::StateKindConflict(state.name.clone()));
+                }
+                state.kind = kind;
+            }
+        }
+
+        // Every symbol the machine can ever see has to be interned before the program is built,
+        // since the size of the transition table depends on the alphabet.
+        let mut alphabet = self.alphabet.clone();
+        let blank = alphabet.intern(blank_symbol);
+        let tape = (self.tape.iter())
+            .map(|name| alphabet.intern(name))
+            .collect::<Vec<_>>();
+        if let Some((left_marker, right_marker)) = self.end_markers {
+            alphabet.intern(left_marker);
+            alphabet.intern(right_marker);
+        }
 
-        let tm = TuringMachine::new(
-            &initial_state,
-            accept_states.as_slice(),
-            &tape,
-            blank_symbol,
+        let program = Program::new(
+            states,
+            initial_state,
+            self.transitions.clone(),
+            alphabet,
+            self.tape_count,
+            blank,
         );
 
-        Ok(tm)
+        Ok((program, tape))
+    }
+}
+impl Default for TuringMachineBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::turing_machine::Status;
+    use crate::{turing_machine::Status, OutputMode};
 
     #[test]
     fn test_builder() -> Result<()> {
@@ -158,11 +487,357 @@ mod tests {
             .unwrap();
 
         let mut tm = tm;
-        while let Some(_) = tm.next() {}
+        tm.run();
 
-        assert_eq!(tm.tape, vec!["1".to_string(), "1".to_string()]);
+        assert_eq!(tm.tape_contents(0), vec!["1".to_string(), "1".to_string()]);
         assert_eq!(tm.status, Status::Accept);
 
         Ok(())
     }
+
+    #[test]
+    fn test_reject_and_stuck() -> Result<()> {
+        // Rejects on a 0 and moves to S on a 1, where it gets stuck unless S stops the machine.
+        let builder = TuringMachineBuilder::new()
+            .add_states(&["A", "R", "S"])
+            .add_transition("A", "R", "0", "0", Direction::Right)?
+            .add_transition("A", "S", "1", "1", Direction::Right)?
+            .add_transition("R", "A", "_", "_", Direction::Left)?
+            .set_initial_state("A")?
+            .set_reject_states(&["R"])?
+            .set_blank_symbol("_");
+
+        let mut tm = builder.clone().set_tape(vec!["0".to_string()]).build()?;
+        assert_eq!(tm.run(), Status::Reject);
+        assert_eq!(tm.steps, 1);
+
+        let mut tm = builder.clone().set_tape(vec!["1".to_string()]).build()?;
+        assert_eq!(tm.run(), Status::Stuck);
+
+        let mut tm = builder
+            .clone()
+            .set_halt_states(&["S"])?
+            .set_tape(vec!["1".to_string()])
+            .build()?;
+        assert_eq!(tm.run(), Status::Halt);
+
+        let error = builder
+            .clone()
+            .set_halt_states(&["R"])?
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "State R is listed as more than one of accept, reject and halt states"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_tape() -> Result<()> {
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["copy", "done"])
+            .add_multi_tape_transition(
+                "copy",
+                "copy",
+                &["1", "_"],
+                &["1", "1"],
+                &[Direction::Right, Direction::Right],
+            )?
+            .add_multi_tape_transition(
+                "copy",
+                "done",
+                &["_", "_"],
+                &["_", "_"],
+                &[Direction::Stay, Direction::Left],
+            )?
+            .set_initial_state("copy")?
+            .set_accept_states(&["done"])?
+            .set_tape(vec!["1".to_string(), "1".to_string()])
+            .set_tape_count(2)
+            .set_blank_symbol("_")
+            .build()?;
+
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.tape_contents(1), vec!["1", "1", "_"]);
+        assert_eq!(tm.tapes[1].head(), 1);
+
+        let result = TuringMachineBuilder::new()
+            .add_states(&["A"])
+            .add_transition("A", "A", "1", "1", Direction::Right)?
+            .set_initial_state("A")?
+            .set_tape_count(2)
+            .set_blank_symbol("_")
+            .build();
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tape_model() -> Result<()> {
+        // Moves left over the 1s and accepts on the blank or the left end marker before them.
+        let builder = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "A", "1", "1", Direction::Left)?
+            .add_transition("A", "B", "_", "_", Direction::Right)?
+            .add_transition("A", "B", "<", "<", Direction::Left)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_tape(vec!["1".to_string(), "1".to_string()])
+            .set_blank_symbol("_");
+
+        let mut tm = builder.clone().build()?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.tape_contents(0), vec!["_", "1", "1"]);
+
+        let mut tm = builder
+            .clone()
+            .set_tape_model(TapeModel::OneWay)
+            .set_max_steps(10)
+            .build()?;
+        assert_eq!(tm.run(), Status::Timeout);
+        assert_eq!(tm.tape_contents(0), vec!["1", "1"]);
+        assert_eq!(tm.tapes[0].head(), 0);
+
+        let mut tm = builder
+            .clone()
+            .set_tape_model(TapeModel::OneWayStrict)
+            .build()?;
+        assert_eq!(tm.run(), Status::OutOfBounds);
+        assert_eq!(tm.tape_contents(0), vec!["1", "1"]);
+
+        let mut tm = builder
+            .clone()
+            .set_tape_model(TapeModel::LinearBounded)
+            .set_end_markers("<", ">")
+            .build()?;
+        assert_eq!(tm.run(), Status::OutOfBounds);
+        assert_eq!(tm.tape_contents(0), vec!["<", "1", "1", ">"]);
+        assert_eq!(tm.tapes[0].head(), -1);
+
+        let tm = builder
+            .clone()
+            .set_tape_model(TapeModel::LinearBounded)
+            .build()?;
+        assert_eq!(tm.tape_contents(0), vec!["<", "1", "1", ">"]);
+        assert!(tm.end_markers.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tape_backend() -> Result<()> {
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C"])
+            .add_transition("A", "A", "1", "0", Direction::Right)?
+            .add_transition("A", "B", "_", "_", Direction::Left)?
+            .add_transition("B", "B", "0", "0", Direction::Left)?
+            .add_transition("B", "C", "_", "_", Direction::Stay)?
+            .set_initial_state("A")?
+            .set_accept_states(&["C"])?
+            .set_tape(vec!["1".to_string(), "1".to_string()])
+            .set_tape_backend(TapeBackend::Sparse)
+            .set_blank_symbol("_")
+            .build()?;
+
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.tape_contents(0), vec!["_", "0", "0", "_"]);
+        assert_eq!(tm.tapes[0].head(), -1);
+        let snapshot = tm.history.get(2).unwrap();
+        assert_eq!(
+            tm.program.alphabet().names(&snapshot.tapes[0].symbols()),
+            vec!["0", "0", "_"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_output() -> Result<()> {
+        // Replaces the 0s with blanks and stops on the first 1 with the head on the second cell.
+        let builder = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "A", "0", "_", Direction::Right)?
+            .add_transition("A", "B", "1", "1", Direction::Stay)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_tape(["0", "1", "0", "ab", "_"].map(String::from).to_vec())
+            .set_blank_symbol("_");
+
+        let mut tm = builder.clone().build()?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.output(0, OutputMode::Trim), "10ab");
+        assert_eq!(tm.output(0, OutputMode::FromHead), "10ab");
+
+        let mut tm = builder
+            .clone()
+            .add_transition("B", "B", "1", "_", Direction::Right)?
+            .build()?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.output(0, OutputMode::Trim), "0ab");
+        assert_eq!(tm.output(0, OutputMode::FromHead), "0ab");
+
+        let mut tm = builder
+            .clone()
+            .add_transition("B", "B", "1", "1", Direction::Left)?
+            .add_transition("B", "B", "_", "_", Direction::Left)?
+            .set_tape_model(TapeModel::LinearBounded)
+            .set_end_markers("<", ">")
+            .build()?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(
+            tm.tape_contents(0),
+            vec!["<", "_", "1", "0", "ab", "_", ">"]
+        );
+        assert_eq!(tm.output(0, OutputMode::Trim), "10ab");
+        assert_eq!(tm.output(0, OutputMode::FromHead), "");
+
+        // Without end markers every cell of a linear bounded tape is part of the result.
+        let mut tm = TuringMachineBuilder::new()
+            .add_state("A")
+            .set_initial_state("A")?
+            .set_accept_states(&["A"])?
+            .set_tape(["1", "2", "3"].map(String::from).to_vec())
+            .set_blank_symbol("_")
+            .build()?;
+        tm.set_tape_model(TapeModel::LinearBounded);
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.output(0, OutputMode::Trim), "123");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_head() -> Result<()> {
+        // Moves left to the first blank and accepts there.
+        let builder = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "A", "1", "0", Direction::Left)?
+            .add_transition("A", "B", "_", "_", Direction::Stay)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_tape(vec!["1".to_string(); 3])
+            .set_blank_symbol("_");
+
+        let mut tm = builder.clone().set_head(2).build()?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.steps, 4);
+        assert_eq!(tm.tape_contents(0), vec!["_", "0", "0", "0"]);
+
+        let mut tm = builder.clone().set_head(5).build()?;
+        assert_eq!(tm.tapes[0].bounds(), 0..6);
+        assert_eq!(tm.history.initial_tapes()[0].head(), 5);
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.steps, 1);
+
+        let mut tm = builder.clone().set_head(-1).build()?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.tape_contents(0), vec!["_", "1", "1", "1"]);
+
+        let tm = builder
+            .clone()
+            .set_head(3)
+            .set_tape_model(TapeModel::LinearBounded)
+            .set_end_markers("<", ">")
+            .build()?;
+        assert_eq!(tm.tapes[0].head(), 3);
+        assert!(builder
+            .clone()
+            .set_head(4)
+            .set_tape_model(TapeModel::LinearBounded)
+            .set_end_markers("<", ">")
+            .build()
+            .is_err());
+        assert!(builder
+            .clone()
+            .set_head(-1)
+            .set_tape_model(TapeModel::OneWay)
+            .build()
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stay() -> Result<()> {
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C"])
+            .add_transition("A", "B", "0", "1", Direction::Stay)?
+            .add_transition("B", "C", "1", "2", Direction::Right)?
+            .set_initial_state("A")?
+            .set_accept_states(&["C"])?
+            .set_tape(vec!["0".to_string()])
+            .set_blank_symbol("_")
+            .build()?;
+
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.tape_contents(0), vec!["2".to_string(), "_".to_string()]);
+        assert_eq!(tm.tapes[0].head(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_steps() -> Result<()> {
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "B", "0", "0", Direction::Right)?
+            .add_transition("B", "A", "0", "0", Direction::Left)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_tape(vec!["0".to_string()])
+            .set_blank_symbol("0")
+            .set_max_steps(10)
+            .build()?;
+
+        assert_eq!(tm.run(), Status::Timeout);
+        assert_eq!(tm.steps, 10);
+        assert_eq!(tm.history.len(), 10);
+
+        // A machine that stops in exactly `max_steps` steps isn't cut off.
+        for (max_steps, status) in [(2, Status::Timeout), (3, Status::Accept)] {
+            let mut tm = TuringMachineBuilder::new()
+                .add_states(&["A", "B"])
+                .add_transition("A", "A", "1", "1", Direction::Right)?
+                .add_transition("A", "B", "_", "_", Direction::Left)?
+                .set_initial_state("A")?
+                .set_accept_states(&["B"])?
+                .set_tape(vec!["1".to_string(); 2])
+                .set_blank_symbol("_")
+                .set_max_steps(max_steps)
+                .build()?;
+            assert_eq!(tm.run(), status);
+            assert_eq!(tm.steps, max_steps);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_detection() -> Result<()> {
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C"])
+            .add_transition("A", "B", "1", "0", Direction::Right)?
+            .add_transition("B", "C", "_", "_", Direction::Left)?
+            .add_transition("C", "B", "0", "0", Direction::Right)?
+            .set_initial_state("A")?
+            .set_accept_states(&["C"])?
+            .set_tape(vec!["1".to_string()])
+            .set_blank_symbol("_")
+            .enable_loop_detection()
+            .build()?;
+
+        assert_eq!(
+            tm.run(),
+            Status::Loop {
+                start: 1,
+                period: 2
+            }
+        );
+        assert_eq!(tm.steps, 3);
+
+        Ok(())
+    }
 }
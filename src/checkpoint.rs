@@ -0,0 +1,262 @@
+use crate::{
+    history::History,
+    oracle::OracleLink,
+    program::Program,
+    tape::TapeModel,
+    turing_machine::{Status, TuringMachine},
+};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    #[error("Checkpoint was saved from a different machine definition")]
+    DefinitionMismatch,
+    #[error("State {0} in the checkpoint is not found")]
+    StateNotFound(String),
+    #[error("Tape symbol {0} in the checkpoint is not in the alphabet")]
+    SymbolNotFound(String),
+    #[error("Checkpoint has {actual} tapes, but the machine has {expected} tapes")]
+    TapeCountMismatch { expected: usize, actual: usize },
+}
+
+// `tape_ptr` is the index of the head in `symbols`, and `start_ptr` the index of the first input
+// cell.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TapeCheckpoint {
+    pub symbols: Vec<String>,
+    pub tape_ptr: usize,
+    pub start_ptr: usize,
+}
+
+// Everything needed to continue a run, with symbols and states stored by name so that a
+// checkpoint doesn't depend on interning order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub definition_hash: u64,
+    pub state: String,
+    pub tapes: Vec<TapeCheckpoint>,
+    pub status: Status,
+    pub steps: usize,
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+impl Checkpoint {
+    pub fn read_from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+
+        serde_json::from_str(&content).context("Invalid checkpoint file")
+    }
+
+    // Writes to a temporary file first, so an interrupted write never leaves a broken checkpoint.
+    pub fn write_to_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+}
+
+impl TuringMachine {
+    pub fn checkpoint(&self) -> Checkpoint {
+        let tapes = self
+            .tapes
+            .iter()
+            .map(|tape| {
+                let start = tape.bounds().start;
+                TapeCheckpoint {
+                    symbols: (self.program.alphabet().names(&tape.symbols()))
+                        .into_iter()
+                        .map(str::to_string)
+                        .collect(),
+                    tape_ptr: (tape.head() - start) as usize,
+                    start_ptr: -start as usize,
+                }
+            })
+            .collect();
+
+        Checkpoint {
+            definition_hash: self.definition_hash(),
+            state: self.current_state_name().to_string(),
+            tapes,
+            status: self.status,
+            steps: self.steps,
+            seed: self.seed,
+        }
+    }
+
+    // The history starts over at the checkpoint, so earlier steps can't be sought back to. A
+    // probabilistic run goes on with the seed it was saved with.
+    pub fn restore(&mut self, checkpoint: &Checkpoint) -> Result<()> {
+        if checkpoint.definition_hash != self.definition_hash() {
+            bail!(CheckpointError::DefinitionMismatch);
+        }
+        if checkpoint.tapes.len() != self.tape_count() {
+            bail!(CheckpointError::TapeCountMismatch {
+                expected: self.tape_count(),
+                actual: checkpoint.tapes.len(),
+            });
+        }
+        let state = (self.program)
+            .state_id(&checkpoint.state)
+            .ok_or_else(|| CheckpointError::StateNotFound(checkpoint.state.clone()))?;
+
+        let mut tapes = vec![];
+        for saved in &checkpoint.tapes {
+            let mut tape = (self.tape_backend).new_tape(&[], self.program.blank());
+            for (i, name) in saved.symbols.iter().enumerate() {
+                let symbol = (self.program)
+                    .symbol(name)
+                    .ok_or_else(|| CheckpointError::SymbolNotFound(name.clone()))?;
+                tape.set(i as isize - saved.start_ptr as isize, symbol);
+            }
+            tape.set_head(saved.tape_ptr as isize - saved.start_ptr as isize);
+            tapes.push(tape);
+        }
+
+        self.current_state = state;
+        self.tapes = tapes;
+        self.status = checkpoint.status;
+        self.steps = checkpoint.steps;
+        self.seed = checkpoint.seed;
+        self.history = History::starting_at(&self.tapes, self.steps);
+        self.started_at = None;
+        self.end_status = None;
+        if let Some(seen_configurations) = self.seen_configurations.as_mut() {
+            seen_configurations.clear();
+        }
+
+        Ok(())
+    }
+
+    fn definition_hash(&self) -> u64 {
+        definition_hash(&self.program, self.tape_model, self.oracle.as_ref())
+    }
+}
+
+// A hash of everything that affects how the machine runs. It has to stay the same across builds
+// and platforms, so it hashes a JSON rendering of the definition with FNV-1a instead of using
+// `std::hash`. Of an oracle only the way it is linked is hashed, as its answers can't be.
+fn definition_hash(program: &Program, tape_model: TapeModel, oracle: Option<&OracleLink>) -> u64 {
+    let name = |id| program.state(id).name.as_str();
+    let states = (program.states())
+        .iter()
+        .map(|s| (s.name.as_str(), format!("{:?}", s.kind)))
+        .collect::<Vec<_>>();
+    let transitions = (program.all_transitions())
+        .iter()
+        .map(|t| {
+            (
+                name(t.from),
+                name(t.to),
+                program.alphabet().names(&t.read),
+                program.alphabet().names(&t.write),
+                format!("{:?}", t.move_head),
+            )
+        })
+        .collect::<Vec<_>>();
+    let weights = (program.all_transitions().iter())
+        .map(|t| t.weight)
+        .collect::<Vec<_>>();
+    let oracle = oracle.map(|link| {
+        (
+            name(link.query_state),
+            name(link.yes_state),
+            name(link.no_state),
+            link.tape,
+            format!("{:?}", link.region),
+        )
+    });
+    let definition = serde_json::json!([
+        format!("{:?}", tape_model),
+        program.tape_count(),
+        program.symbol_name(program.blank()),
+        name(program.initial_state()),
+        states,
+        transitions,
+        weights,
+        oracle,
+    ]);
+
+    definition
+        .to_string()
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Direction, OutputMode, TapeBackend, TuringMachineBuilder};
+    use anyhow::Result;
+
+    fn builder() -> Result<TuringMachineBuilder<'static>> {
+        let builder = TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C"])
+            .add_transition("A", "A", "1", "0", Direction::Right)?
+            .add_transition("A", "C", "_", "_", Direction::Left)?
+            .add_transition("C", "C", "0", "0", Direction::Left)?
+            .add_transition("C", "B", "_", "1", Direction::Stay)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_tape(vec!["1".to_string(); 3])
+            .set_blank_symbol("_");
+
+        Ok(builder)
+    }
+
+    #[test]
+    fn test_checkpoint() -> Result<()> {
+        let mut tm = builder()?.build()?;
+        tm.run_with_limit(Some(7), None);
+        let checkpoint = tm.checkpoint();
+        assert_eq!(checkpoint.state, "C");
+        assert_eq!(checkpoint.steps, 7);
+        assert_eq!(checkpoint.tapes[0].symbols, vec!["_", "0", "0", "0", "_"]);
+        assert_eq!(checkpoint.tapes[0].tape_ptr, 0);
+        assert_eq!(checkpoint.tapes[0].start_ptr, 1);
+
+        let json = serde_json::to_string(&checkpoint)?;
+        let checkpoint = serde_json::from_str::<Checkpoint>(&json)?;
+        let mut resumed = builder()?.set_tape_backend(TapeBackend::Sparse).build()?;
+        resumed.restore(&checkpoint)?;
+        resumed.status = Status::Running;
+        assert_eq!(resumed.run(), Status::Accept);
+        assert_eq!(resumed.steps, 8);
+        assert_eq!(resumed.history.first_step(), 7);
+        assert_eq!(resumed.tape_contents(0), vec!["1", "0", "0", "0", "_"]);
+
+        tm.status = Status::Running;
+        tm.run_with_limit(None, None);
+        assert_eq!(tm.checkpoint(), resumed.checkpoint());
+
+        let mut other = builder()?
+            .add_transition("B", "A", "0", "0", Direction::Stay)?
+            .build()?;
+        assert!(other.restore(&checkpoint).is_err());
+
+        // The oracle is part of the definition, down to the region it reads the query from.
+        let oracle = |_: &[&str]| true;
+        let trimmed = builder()?
+            .add_states(&["Q", "Y", "N"])
+            .set_oracle(oracle, "Q", "Y", "N")?
+            .set_query_tape(0, OutputMode::Trim)
+            .build()?;
+        let mut from_head = builder()?
+            .add_states(&["Q", "Y", "N"])
+            .set_oracle(oracle, "Q", "Y", "N")?
+            .set_query_tape(0, OutputMode::FromHead)
+            .build()?;
+        assert!(from_head.restore(&trimmed.checkpoint()).is_err());
+        assert!(builder()?.build()?.restore(&trimmed.checkpoint()).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+use crate::{
+    history::History,
+    program::Program,
+    state::{Direction, State, StateId, StateKind, Transition},
+    symbol::Symbol,
+    turing_machine::TuringMachine,
+};
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ComposeError {
+    #[error("Machines use different blank symbols {0} and {1}")]
+    BlankMismatch(String, String),
+    #[error("Machines have {0} and {1} tapes")]
+    TapeCountMismatch(usize, usize),
+    #[error("Machines with an oracle cannot be composed")]
+    Oracle,
+}
+
+// What becomes of the states of one kind when a machine is part of a composition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Link {
+    Keep,
+    // Entering such a state enters the initial state of another part instead.
+    Enter(usize),
+    Become(StateKind),
+}
+
+impl TuringMachine {
+    // A machine that runs `self` and then, if it accepts, `next` on the tapes it left behind.
+    pub fn then(&self, next: &TuringMachine) -> Result<TuringMachine> {
+        let parts = [("first", self), ("second", next)];
+        let program = compose(&parts, |part, kind| match (part, kind) {
+            (0, StateKind::Accept) => Link::Enter(1),
+            _ => Link::Keep,
+        })?;
+
+        Ok(self.with_program(program))
+    }
+
+    // A machine that runs `self` and then `on_accept` or `on_reject` depending on how it stopped.
+    // Getting stuck outside an accept state counts as rejecting, just like a declared reject state.
+    pub fn branch(
+        &self,
+        on_accept: &TuringMachine,
+        on_reject: &TuringMachine,
+    ) -> Result<TuringMachine> {
+        let parts = [("test", self), ("accept", on_accept), ("reject", on_reject)];
+        let program = compose(&parts, |part, kind| match (part, kind) {
+            (0, StateKind::Accept) => Link::Enter(1),
+            (0, StateKind::Reject | StateKind::Normal) => Link::Enter(2),
+            _ => Link::Keep,
+        })?;
+
+        Ok(self.with_program(program))
+    }
+
+    // A machine that runs `self` again every time it accepts, and accepts once it rejects.
+    pub fn repeat(&self) -> Result<TuringMachine> {
+        let parts = [("body", self)];
+        let program = compose(&parts, |_, kind| match kind {
+            StateKind::Accept => Link::Enter(0),
+            StateKind::Reject => Link::Become(StateKind::Accept),
+            _ => Link::Keep,
+        })?;
+
+        Ok(self.with_program(program))
+    }
+
+    // The composed program extends the alphabet of `self`, so its initial tapes can be reused as
+    // they are.
+    fn with_program(&self, program: Program) -> TuringMachine {
+        let tapes = self.history.initial_tapes().to_vec();
+        let mut tm = TuringMachine::with_tape_backend(Arc::new(program), &[], self.tape_backend);
+        tm.history = History::new(&tapes);
+        tm.tapes = tapes;
+        tm.tape_model = self.tape_model;
+        tm.end_markers = self.end_markers;
+        tm.max_steps = self.max_steps;
+        tm.timeout = self.timeout;
+        tm.seed = self.seed;
+        if self.seen_configurations.is_some() {
+            tm.enable_loop_detection();
+        }
+
+        tm
+    }
+}
+
+// Puts the states of every part side by side, named `prefix::state`, and starts in the initial
+// state of the first part. A machine only stops in an accept state once none of its transitions
+// applies, so a linked state without transitions is merged into the state it leads to, and any
+// other linked state moves there in one extra step when it would have got stuck.
+fn compose(
+    machines: &[(&str, &TuringMachine)],
+    link: impl Fn(usize, StateKind) -> Link,
+) -> Result<Program> {
+    if machines.iter().any(|(_, tm)| tm.oracle.is_some()) {
+        bail!(ComposeError::Oracle);
+    }
+    let parts = (machines.iter())
+        .map(|&(prefix, tm)| (prefix, &*tm.program))
+        .collect::<Vec<_>>();
+    let (_, first) = parts[0];
+    for &(_, program) in &parts {
+        if program.tape_count() != first.tape_count() {
+            bail!(ComposeError::TapeCountMismatch(
+                first.tape_count(),
+                program.tape_count()
+            ));
+        }
+        let (blank, other) = (
+            first.symbol_name(first.blank()),
+            program.symbol_name(program.blank()),
+        );
+        if blank != other {
+            bail!(ComposeError::BlankMismatch(
+                blank.to_string(),
+                other.to_string()
+            ));
+        }
+    }
+
+    let mut offsets = vec![];
+    let mut states = vec![];
+    for (i, &(prefix, program)) in parts.iter().enumerate() {
+        offsets.push(states.len());
+        states.extend(program.states().iter().map(|state| State {
+            name: format!("{prefix}::{}", state.name),
+            kind: match link(i, state.kind) {
+                Link::Keep => state.kind,
+                Link::Enter(_) => StateKind::Normal,
+                Link::Become(kind) => kind,
+            },
+        }));
+    }
+
+    let linked_to = |part: usize, state: StateId| match link(part, parts[part].1.state(state).kind)
+    {
+        Link::Enter(next) => Some(next),
+        _ => None,
+    };
+    let is_final = |part: usize, state: StateId| {
+        !(parts[part].1.all_transitions().iter()).any(|t| t.from == state)
+    };
+    // A state with a transition that reads only wildcards never gets stuck.
+    let is_total = |part: usize, state: StateId| {
+        (parts[part].1.all_transitions().iter())
+            .any(|t| t.from == state && t.wildcard_count() == t.read.len())
+    };
+    // Follows links out of final states. A cycle of initial states that all link onwards ends up
+    // in one of them, where the machine gets stuck.
+    let resolve = |mut part: usize, mut state: StateId| {
+        for _ in 0..=parts.len() {
+            match linked_to(part, state) {
+                Some(next) if is_final(part, state) => {
+                    (part, state) = (next, parts[next].1.initial_state())
+                }
+                _ => break,
+            }
+        }
+        StateId(offsets[part] + state.0)
+    };
+
+    let mut alphabet = first.alphabet().clone();
+    let mut transitions = vec![];
+    for (i, &(_, program)) in parts.iter().enumerate() {
+        for transition in program.all_transitions() {
+            // Reject and halt states stop the machine before any transition is taken.
+            let kind = program.state(transition.from).kind;
+            if matches!(kind, StateKind::Reject | StateKind::Halt) {
+                continue;
+            }
+
+            let mut intern = |symbols: &[Symbol]| {
+                (symbols.iter())
+                    .map(|&s| match s {
+                        Symbol::ANY => s,
+                        s => alphabet.intern(program.symbol_name(s)),
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let read = intern(&transition.read);
+            let write = intern(&transition.write);
+            transitions.push(
+                Transition::new_multi_tape(
+                    StateId(offsets[i] + transition.from.0),
+                    resolve(i, transition.to),
+                    &read,
+                    &write,
+                    &transition.move_head,
+                )
+                .with_weight(transition.weight),
+            );
+        }
+
+        for id in (0..program.states().len()).map(StateId) {
+            if let Some(next) = linked_to(i, id).filter(|_| !is_final(i, id) && !is_total(i, id)) {
+                let any = vec![Symbol::ANY; first.tape_count()];
+                transitions.push(Transition::new_multi_tape(
+                    StateId(offsets[i] + id.0),
+                    resolve(next, parts[next].1.initial_state()),
+                    &any,
+                    &any,
+                    &vec![Direction::Stay; first.tape_count()],
+                ));
+            }
+        }
+    }
+
+    Ok(Program::new(
+        states,
+        resolve(0, first.initial_state()),
+        transitions,
+        alphabet,
+        first.tape_count(),
+        first.blank(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OutputMode, Status, TuringMachineBuilder};
+
+    // Moves right over `symbol`s and accepts on the first blank, or rejects on anything else.
+    fn skip(symbol: &str, other: &str, tape: &[&str]) -> Result<TuringMachine> {
+        TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C"])
+            .add_transition("A", "A", symbol, symbol, Direction::Right)?
+            .add_transition("A", "B", "_", "_", Direction::Left)?
+            .add_transition("A", "C", other, other, Direction::Stay)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_reject_states(&["C"])?
+            .set_tape(tape.iter().map(|s| s.to_string()).collect())
+            .set_blank_symbol("_")
+            .build()
+    }
+
+    // Overwrites the cell under the head with `symbol` and accepts.
+    fn write(symbol: &str) -> Result<TuringMachine> {
+        TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "B", "*", symbol, Direction::Stay)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_blank_symbol("_")
+            .build()
+    }
+
+    #[test]
+    fn test_then() -> Result<()> {
+        let mut tm = skip("1", "0", &["1", "1"])?.then(&write("x")?)?;
+        assert_eq!(tm.current_state_name(), "first::A");
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.current_state_name(), "second::B");
+        assert_eq!(tm.tape_contents(0), vec!["1", "x", "_"]);
+
+        let mut tm = skip("1", "0", &["1", "0"])?.then(&write("x")?)?;
+        assert_eq!(tm.run(), Status::Reject);
+        assert_eq!(tm.tape_contents(0), vec!["1", "0"]);
+
+        // B accepts once it reaches a blank, after moving left over 1s.
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "B", "1", "1", Direction::Right)?
+            .add_transition("B", "B", "1", "1", Direction::Left)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_tape(vec!["1".to_string(); 2])
+            .set_blank_symbol("_")
+            .build()?
+            .then(&write("x")?)?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.steps, 5);
+        assert_eq!(tm.tape_contents(0), vec!["x", "1", "1"]);
+
+        let other_blank = TuringMachineBuilder::new()
+            .add_state("A")
+            .set_initial_state("A")?
+            .set_blank_symbol("#")
+            .build()?;
+        assert!(write("x")?.then(&other_blank).is_err());
+
+        let with_oracle = TuringMachineBuilder::new()
+            .add_states(&["Q", "Y", "N"])
+            .set_initial_state("Q")?
+            .set_blank_symbol("_")
+            .set_oracle(|_: &[&str]| true, "Q", "Y", "N")?
+            .set_query_tape(0, OutputMode::Trim)
+            .build()?;
+        assert!(with_oracle.then(&write("x")?).is_err());
+        assert!(write("x")?.then(&with_oracle).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_branch() -> Result<()> {
+        let test = skip("1", "0", &["1", "0"])?;
+        let mut tm = test.branch(&write("a")?, &write("r")?)?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.current_state_name(), "reject::B");
+        assert_eq!(tm.tape_contents(0), vec!["1", "r"]);
+
+        // A test that rejects by getting stuck on the 0 takes the reject branch as well.
+        let stuck = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "A", "1", "1", Direction::Right)?
+            .add_transition("A", "B", "_", "_", Direction::Left)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_tape(vec!["1".to_string(), "0".to_string()])
+            .set_blank_symbol("_")
+            .build()?;
+        let mut tm = stuck.branch(&write("a")?, &write("r")?)?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.current_state_name(), "reject::B");
+        assert_eq!(tm.tape_contents(0), vec!["1", "r"]);
+
+        let mut tm = skip("1", "0", &["1"])?.branch(&write("a")?, &write("r")?)?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.current_state_name(), "accept::B");
+        assert_eq!(tm.tape_contents(0), vec!["a", "_"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeat() -> Result<()> {
+        // Each round turns the first 1 into an x, and the loop ends once there is none left.
+        let body = TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C", "D"])
+            .add_transition("A", "A", "x", "x", Direction::Right)?
+            .add_transition("A", "B", "1", "x", Direction::Left)?
+            .add_transition("A", "C", "_", "_", Direction::Left)?
+            .add_transition("B", "B", "x", "x", Direction::Left)?
+            .add_transition("B", "D", "_", "_", Direction::Right)?
+            .set_initial_state("A")?
+            .set_accept_states(&["D"])?
+            .set_reject_states(&["C"])?
+            .set_tape(vec!["1".to_string(); 3])
+            .set_blank_symbol("_")
+            .build()?;
+        let mut tm = body.repeat()?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.current_state_name(), "body::C");
+        assert_eq!(tm.tape_contents(0), vec!["_", "x", "x", "x", "_"]);
+
+        Ok(())
+    }
+}
@@ -1,19 +1,75 @@
-use crate::Direction;
-use anyhow::{anyhow, Context, Result};
+use crate::{Direction, OutputMode, Symbol, TapeModel};
+use anyhow::{anyhow, bail, Context, Result};
 use serde::Deserialize;
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fs,
-    path::Path,
+    path::{Path, PathBuf},
     thread::{self, JoinHandle},
 };
+use thiserror::Error;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Symbol class @{0} is not defined")]
+    ClassNotFound(String),
+    #[error("Variable ${variable} in transition from \"{from}\" is not bound by read or vars")]
+    UnboundVariable { from: String, variable: String },
+    #[error("Machine file \"{0}\" imports itself")]
+    RecursiveImport(PathBuf),
+    #[error("Imported machine {name} has a different {field}")]
+    ImportMismatch { name: String, field: &'static str },
+    #[error("Imported machine {0} queries an oracle, which only the importing machine can do")]
+    ImportedOracle(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+impl<T: Clone> OneOrMany<T> {
+    pub fn to_vec(&self) -> Vec<T> {
+        match self {
+            OneOrMany::One(value) => vec![value.clone()],
+            OneOrMany::Many(values) => values.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct ConfigTransition {
     pub from: String,
     pub to: String,
-    pub read: String,
-    pub write: String,
-    pub direction: Direction,
+    pub read: OneOrMany<String>,
+    pub write: OneOrMany<String>,
+    pub direction: OneOrMany<Direction>,
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+// A machine imported from another file. Its states are renamed to `name::state`, and entering
+// one of its accept states enters `on_accept` instead.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConfigMachine {
+    pub file: PathBuf,
+    pub on_accept: String,
+}
+
+// The states through which a machine asks an oracle, and where it writes its queries. `region`
+// picks the query out of `tape` the same way an output mode picks out a result.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConfigOracle {
+    pub query: String,
+    pub yes: String,
+    pub no: String,
+    #[serde(default)]
+    pub tape: usize,
+    #[serde(default)]
+    pub region: OutputMode,
 }
 
 #[derive(Debug, Deserialize)]
@@ -21,12 +77,37 @@ pub struct Config {
     pub transitions: Vec<ConfigTransition>,
     pub initial_state: String,
     pub accept_states: Vec<String>,
+    #[serde(default)]
+    pub reject_states: Vec<String>,
+    #[serde(default)]
+    pub halt_states: Vec<String>,
     pub blank: String,
+    #[serde(default = "default_tapes")]
+    pub tapes: usize,
+    #[serde(default)]
+    pub grid: bool,
+    #[serde(default)]
+    pub tape_model: TapeModel,
+    #[serde(default = "default_end_markers")]
+    pub end_markers: (String, String),
+    #[serde(default)]
+    pub head: isize,
+    #[serde(default)]
+    pub symbols: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub machines: BTreeMap<String, ConfigMachine>,
+    #[serde(default)]
+    pub oracle: Option<ConfigOracle>,
 }
 impl Config {
     pub fn read_from_file(path: impl AsRef<Path>) -> Result<Self> {
-        let path = path.as_ref();
-        let content = fs::read_to_string(path)?;
+        Self::read_importing(path.as_ref(), &mut vec![])
+    }
+
+    // `importing` holds the files whose imports are being read, to reject import cycles.
+    fn read_importing(path: &Path, importing: &mut Vec<PathBuf>) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read \"{}\"", path.display()))?;
 
         let mut handles = Vec::new();
         for file_type in ["json", "toml", "yaml"] {
@@ -49,6 +130,432 @@ impl Config {
             }
         });
 
-        config.ok_or_else(|| anyhow!("Invalid config file"))
+        let mut config = config.ok_or_else(|| anyhow!("Invalid config file"))?;
+        config.expand_transitions()?;
+
+        let canonical = path.canonicalize()?;
+        if importing.contains(&canonical) {
+            bail!(ConfigError::RecursiveImport(path.to_path_buf()));
+        }
+        importing.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for (name, machine) in config.machines.clone() {
+            let imported = Self::read_importing(&dir.join(&machine.file), importing)?;
+            config.import(&name, &machine, imported)?;
+        }
+        importing.pop();
+
+        Ok(config)
+    }
+
+    // Adds the states and transitions of `imported` under the namespace `name`. A transition to
+    // `name`, or an initial state of `name`, enters the initial state of the imported machine.
+    // Its reject and halt states stop this machine as well.
+    pub fn import(&mut self, name: &str, machine: &ConfigMachine, imported: Config) -> Result<()> {
+        let mismatch = |field| ConfigError::ImportMismatch {
+            name: name.to_string(),
+            field,
+        };
+        if imported.blank != self.blank {
+            bail!(mismatch("blank symbol"));
+        }
+        if imported.tapes != self.tapes {
+            bail!(mismatch("number of tapes"));
+        }
+        if imported.grid != self.grid {
+            bail!(mismatch("tape dimension"));
+        }
+        if imported.oracle.is_some() {
+            bail!(ConfigError::ImportedOracle(name.to_string()));
+        }
+
+        // A machine only accepts once it is stuck in an accept state. Accept states without
+        // transitions are merged into `on_accept`, and the others move there in one extra step
+        // when none of their own transitions applies.
+        let is_final = |state: &str| {
+            imported.accept_states.iter().any(|s| s == state)
+                && !imported.transitions.iter().any(|t| t.from == state)
+        };
+        let rename = |state: &str| {
+            if is_final(state) {
+                machine.on_accept.clone()
+            } else {
+                format!("{name}::{state}")
+            }
+        };
+        let entry = rename(&imported.initial_state);
+        for transition in &mut self.transitions {
+            if transition.to == name {
+                transition.to = entry.clone();
+            }
+        }
+        if self.initial_state == name {
+            self.initial_state = entry;
+        }
+
+        let mut transitions = (imported.transitions)
+            .iter()
+            .map(|t| ConfigTransition {
+                from: rename(&t.from),
+                to: rename(&t.to),
+                ..t.clone()
+            })
+            .collect::<Vec<_>>();
+        for state in &imported.accept_states {
+            if !is_final(state) {
+                let any = vec![Symbol::ANY_NAME.to_string(); self.tapes];
+                transitions.push(ConfigTransition {
+                    from: rename(state),
+                    to: machine.on_accept.clone(),
+                    read: OneOrMany::Many(any.clone()),
+                    write: OneOrMany::Many(any),
+                    direction: OneOrMany::Many(vec![Direction::Stay; self.tapes]),
+                    vars: BTreeMap::new(),
+                    weight: 1,
+                });
+            }
+        }
+        self.transitions.extend(transitions);
+        self.reject_states
+            .extend(imported.reject_states.iter().map(|s| rename(s)));
+        self.halt_states
+            .extend(imported.halt_states.iter().map(|s| rename(s)));
+
+        Ok(())
+    }
+
+    // Replaces every transition that reads a symbol class (`@digit`) or uses variables (`$x`) with
+    // one plain transition per symbol they stand for. A variable is bound by reading `$x@digit`,
+    // which matches the symbols of the class, by reading `$x`, which matches any symbol or the
+    // symbols of the class given in `vars`, or by `vars` alone. Its value can then be used in
+    // `from`, `to` and `write`.
+    pub fn expand_transitions(&mut self) -> Result<()> {
+        let alphabet = self.alphabet();
+        let mut transitions = vec![];
+        for transition in &self.transitions {
+            transitions.extend(self.expand_transition(transition, &alphabet)?);
+        }
+        self.transitions = transitions;
+
+        Ok(())
+    }
+
+    // Every symbol that can be written down without a pattern.
+    fn alphabet(&self) -> Vec<String> {
+        let is_pattern = |s: &String| s == Symbol::ANY_NAME || s.starts_with(['@', '$']);
+        let symbols = (self.transitions)
+            .iter()
+            .flat_map(|t| [t.read.to_vec(), t.write.to_vec()].concat())
+            .filter(|s| !is_pattern(s))
+            .chain(self.symbols.values().flatten().cloned())
+            .chain([self.blank.clone()])
+            .collect::<BTreeSet<_>>();
+
+        symbols.into_iter().collect()
+    }
+
+    fn class(&self, name: &str) -> Result<&[String]> {
+        let name = name.trim_start_matches('@');
+        self.symbols
+            .get(name)
+            .map(Vec::as_slice)
+            .ok_or(anyhow!(ConfigError::ClassNotFound(name.to_string())))
+    }
+
+    fn expand_transition(
+        &self,
+        transition: &ConfigTransition,
+        alphabet: &[String],
+    ) -> Result<Vec<ConfigTransition>> {
+        let read = transition.read.to_vec();
+        let write = transition.write.to_vec();
+
+        let mut domains = BTreeMap::new();
+        for (variable, class) in &transition.vars {
+            domains.insert(variable.as_str(), self.class(class)?);
+        }
+        for symbol in &read {
+            if let Some(pattern) = symbol.strip_prefix('$') {
+                match pattern.split_once('@') {
+                    Some((variable, class)) => domains.insert(variable, self.class(class)?),
+                    None => domains.insert(pattern, domains.get(pattern).unwrap_or(&alphabet)),
+                };
+            }
+        }
+        // A read symbol that is not a pattern can still use the variables bound by the others.
+        let plain_reads = read.iter().filter(|s| !s.starts_with(['$', '@']));
+        for text in [&transition.from, &transition.to]
+            .into_iter()
+            .chain(plain_reads)
+            .chain(&write)
+        {
+            if let Some(variable) = variables(text).find(|v| !domains.contains_key(v)) {
+                bail!(ConfigError::UnboundVariable {
+                    from: transition.from.clone(),
+                    variable: variable.to_string(),
+                });
+            }
+        }
+
+        let mut bindings = vec![BTreeMap::new()];
+        for (&variable, &domain) in &domains {
+            bindings = bindings
+                .into_iter()
+                .flat_map(|binding| {
+                    domain.iter().map(move |value| {
+                        let mut binding = binding.clone();
+                        binding.insert(variable, value.as_str());
+                        binding
+                    })
+                })
+                .collect();
+        }
+
+        let mut expanded = vec![];
+        for binding in &bindings {
+            let mut reads = vec![vec![]];
+            for symbol in &read {
+                let bound = symbol.strip_prefix('$').and_then(|p| p.split_once('@'));
+                let choices = match (symbol.strip_prefix('@'), bound) {
+                    (Some(class), _) => self.class(class)?.to_vec(),
+                    (None, Some((variable, _))) => vec![binding[variable].to_string()],
+                    (None, None) => vec![substitute(symbol, binding)],
+                };
+                reads = reads
+                    .into_iter()
+                    .flat_map(|read: Vec<String>| {
+                        choices.iter().map(move |choice| {
+                            let mut read = read.clone();
+                            read.push(choice.clone());
+                            read
+                        })
+                    })
+                    .collect();
+            }
+
+            for read in reads {
+                expanded.push(ConfigTransition {
+                    from: substitute(&transition.from, binding),
+                    to: substitute(&transition.to, binding),
+                    read: OneOrMany::Many(read),
+                    write: OneOrMany::Many(write.iter().map(|s| substitute(s, binding)).collect()),
+                    direction: transition.direction.clone(),
+                    vars: BTreeMap::new(),
+                    weight: transition.weight,
+                });
+            }
+        }
+
+        Ok(expanded)
+    }
+}
+
+// Variables are written `$name` or `${name}`, where a name is made of letters, digits and `_`.
+fn split_variables(text: &str) -> Vec<Result<&str, &str>> {
+    let mut parts = vec![];
+    let mut rest = text;
+    while let Some(i) = rest.find('$') {
+        parts.push(Ok(&rest[..i]));
+        let after = &rest[i + 1..];
+        let (variable, len) = match after.strip_prefix('{').and_then(|s| s.split_once('}')) {
+            Some((variable, _)) => (variable, variable.len() + 2),
+            None => {
+                let len = after
+                    .find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(after.len());
+                (&after[..len], len)
+            }
+        };
+        if variable.is_empty() {
+            parts.push(Ok("$"));
+        } else {
+            parts.push(Err(variable));
+        }
+        rest = &after[len..];
+    }
+    parts.push(Ok(rest));
+
+    parts
+}
+
+fn variables(text: &str) -> impl Iterator<Item = &str> {
+    split_variables(text).into_iter().filter_map(Result::err)
+}
+
+fn substitute(text: &str, binding: &BTreeMap<&str, &str>) -> String {
+    split_variables(text)
+        .into_iter()
+        .map(|part| match part {
+            Ok(text) => text,
+            Err(variable) => binding[variable],
+        })
+        .collect()
+}
+
+fn default_tapes() -> usize {
+    1
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_end_markers() -> (String, String) {
+    ("<".to_string(), ">".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Renders each expanded transition as `from -> to: read / write`.
+    fn expand(content: &str) -> Result<Vec<String>> {
+        let mut config = toml::from_str::<Config>(content)?;
+        config.expand_transitions()?;
+
+        let transitions = (config.transitions)
+            .iter()
+            .map(|t| {
+                let (read, write) = (t.read.to_vec(), t.write.to_vec());
+                format!(
+                    "{} -> {}: {} / {}",
+                    t.from,
+                    t.to,
+                    read.join(","),
+                    write.join(",")
+                )
+            })
+            .collect();
+        Ok(transitions)
+    }
+
+    #[test]
+    fn test_expand_transitions() -> Result<()> {
+        let transitions = expand(
+            r#"
+            initial_state = "A"
+            accept_states = ["B"]
+            blank = "_"
+            transitions = [
+                { from = "A", to = "carry_$x", read = "$x@bit", write = "_", direction = "R" },
+                { from = "carry_$x", to = "B", read = "@bit", write = "${x}", direction = "L", vars = { x = "bit" } },
+            ]
+
+            [symbols]
+            bit = ["0", "1"]
+            "#,
+        )?;
+
+        assert_eq!(
+            transitions,
+            vec![
+                "A -> carry_0: 0 / _",
+                "A -> carry_1: 1 / _",
+                "carry_0 -> B: 0 / 0",
+                "carry_0 -> B: 1 / 0",
+                "carry_1 -> B: 0 / 1",
+                "carry_1 -> B: 1 / 1",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import() -> Result<()> {
+        let mut config = toml::from_str::<Config>(
+            r#"
+            initial_state = "A"
+            accept_states = ["B"]
+            blank = "_"
+            transitions = [
+                { from = "A", to = "skip", read = "1", write = "1", direction = "R" },
+            ]
+            "#,
+        )?;
+        let imported = toml::from_str::<Config>(
+            r#"
+            initial_state = "A"
+            accept_states = ["done", "ok"]
+            reject_states = ["fail"]
+            blank = "_"
+            transitions = [
+                { from = "A", to = "A", read = "1", write = "1", direction = "R" },
+                { from = "A", to = "done", read = "_", write = "_", direction = "L" },
+                { from = "A", to = "fail", read = "0", write = "0", direction = "S" },
+                { from = "A", to = "ok", read = "2", write = "2", direction = "S" },
+                { from = "ok", to = "A", read = "1", write = "1", direction = "S" },
+            ]
+            "#,
+        )?;
+        let machine = ConfigMachine {
+            file: PathBuf::from("skip.toml"),
+            on_accept: "B".to_string(),
+        };
+        config.import("skip", &machine, imported)?;
+
+        let transitions = (config.transitions)
+            .iter()
+            .map(|t| format!("{} -> {}", t.from, t.to))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            transitions,
+            vec![
+                "A -> skip::A",
+                "skip::A -> skip::A",
+                "skip::A -> B",
+                "skip::A -> skip::fail",
+                "skip::A -> skip::ok",
+                "skip::ok -> skip::A",
+                "skip::ok -> B",
+            ]
+        );
+        assert_eq!(config.reject_states, vec!["skip::fail"]);
+
+        let imported = toml::from_str::<Config>(
+            r#"
+            initial_state = "A"
+            accept_states = ["A"]
+            blank = "0"
+            transitions = []
+            "#,
+        )?;
+        assert!(config.import("other", &machine, imported).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_transitions_error() {
+        let config = |transition: &str| {
+            format!(
+                r#"
+                initial_state = "A"
+                accept_states = ["A"]
+                blank = "_"
+                transitions = [{transition}]
+                "#
+            )
+        };
+
+        let result = expand(&config(
+            r#"{ from = "A", to = "A", read = "@digit", write = "_", direction = "R" }"#,
+        ));
+        assert!(result.is_err());
+
+        let result = expand(&config(
+            r#"{ from = "A", to = "B_$y", read = "$x", write = "$x", direction = "R" }"#,
+        ));
+        assert!(result.is_err());
+
+        let result = expand(&config(
+            r#"{ from = "A", to = "A", read = "q$y", write = "_", direction = "R" }"#,
+        ));
+        assert!(result.is_err());
+
+        let transitions = expand(&config(
+            r#"{ from = "A", to = "A", read = "$x", write = "$x", direction = "R" }"#,
+        ));
+        assert_eq!(transitions.unwrap().len(), 1);
     }
 }
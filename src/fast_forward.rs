@@ -0,0 +1,474 @@
+use crate::{
+    history::History,
+    program::Program,
+    state::{Direction, StateId, StateKind},
+    symbol::Symbol,
+    tape::TapeModel,
+    turing_machine::{is_limit_exceeded, Status, TuringMachine},
+};
+use anyhow::{bail, Result};
+use std::{collections::HashMap, time::Instant};
+use thiserror::Error;
+
+// The number of steps simulated inside a single macro cell before the time limit is checked
+// again.
+const CHUNK_STEPS: usize = 1 << 16;
+
+// The number of cells an endless sweep over blanks advances at once when there is no step limit.
+const ENDLESS_SWEEP_CELLS: usize = 1 << 20;
+
+#[derive(Debug, Error)]
+pub enum FastForwardError {
+    #[error("Fast-forward only supports single-tape machines")]
+    MultiTape,
+    #[error("Fast-forward only supports the two-way tape model")]
+    TapeModel,
+    #[error("Fast-forward does not support loop detection")]
+    LoopDetection,
+    #[error("Fast-forward does not support probabilistic machines")]
+    Probabilistic,
+    #[error("Fast-forward does not support oracles")]
+    Oracle,
+    #[error("Macro cells must be at least one cell wide")]
+    MacroSize,
+}
+
+// `macro_size` consecutive tape cells, which the engine treats as a single symbol.
+type Cell = Box<[Symbol]>;
+
+// What the machine did inside one macro cell, starting in `state` with the head at `position`.
+// The head has left the cell if `position` is -1 or `macro_size`, and otherwise the machine
+// stopped with `status`, or ran out of steps while `status` is still `Running`. `low` and `high`
+// are the leftmost and rightmost positions the head has been at, counting the one it left to.
+#[derive(Debug, Clone)]
+struct MacroStep {
+    state: StateId,
+    position: isize,
+    low: isize,
+    high: isize,
+    cell: Cell,
+    steps: usize,
+    status: Status,
+}
+
+// A tape made of runs of equal macro cells. Runs are stacked outwards from the head, so the
+// nearest run on either side is the last one, and everything past the last run is blank.
+#[derive(Debug, Clone)]
+struct RunLengthTape {
+    left: Vec<(Cell, usize)>,
+    right: Vec<(Cell, usize)>,
+    head: Cell,
+    index: isize,
+    blank: Cell,
+}
+impl RunLengthTape {
+    fn side(&mut self, direction: Direction) -> &mut Vec<(Cell, usize)> {
+        match direction {
+            Direction::Left => &mut self.left,
+            _ => &mut self.right,
+        }
+    }
+
+    fn push(runs: &mut Vec<(Cell, usize)>, cell: Cell, count: usize) {
+        match runs.last_mut() {
+            Some((last, last_count)) if *last == cell => *last_count += count,
+            _ => runs.push((cell, count)),
+        }
+    }
+
+    // Removes `count` cells that are known to be equal from the side of the head. Cells past the
+    // last run are blank, so there is nothing to remove there.
+    fn remove(runs: &mut Vec<(Cell, usize)>, count: usize) {
+        if let Some((_, last_count)) = runs.last_mut() {
+            if *last_count > count {
+                *last_count -= count;
+            } else {
+                runs.pop();
+            }
+        }
+    }
+
+    // The number of cells equal to `cell` right next to the head, or `None` if they go on forever.
+    fn run_length(&self, direction: Direction, cell: &Cell) -> Option<usize> {
+        let runs = match direction {
+            Direction::Left => &self.left,
+            _ => &self.right,
+        };
+        match runs.as_slice() {
+            [] if *cell == self.blank => None,
+            [(last, _)] if *last == self.blank && *cell == self.blank => None,
+            [.., (last, count)] if last == cell => Some(*count),
+            _ => Some(0),
+        }
+    }
+
+    // Leaves `count` copies of `cell` behind the head as it moves `count` cells in `direction`.
+    // Every cell passed over but the last one is known to equal the current head cell.
+    fn sweep(&mut self, direction: Direction, cell: Cell, count: usize) {
+        let behind = if direction == Direction::Left {
+            Direction::Right
+        } else {
+            Direction::Left
+        };
+        Self::push(self.side(behind), cell, count);
+        let ahead = self.side(direction);
+        Self::remove(ahead, count - 1);
+        let head = match ahead.last_mut() {
+            Some((cell, 1)) => {
+                let cell = cell.clone();
+                ahead.pop();
+                Some(cell)
+            }
+            Some((cell, count)) => {
+                *count -= 1;
+                Some(cell.clone())
+            }
+            None => None,
+        };
+        self.head = head.unwrap_or_else(|| self.blank.clone());
+        self.index += match direction {
+            Direction::Left => -(count as isize),
+            _ => count as isize,
+        };
+    }
+}
+
+impl TuringMachine {
+    // Runs the machine like `run`, but on a run-length encoded tape of `macro_size`-cell macro
+    // symbols. Whenever the head sweeps over a run of equal macro cells in the same state, the
+    // whole run is applied in one jump. The final configuration, status and step count are the
+    // same as with `run`, but the steps in between are not recorded in the history.
+    pub fn fast_forward(&mut self, macro_size: usize) -> Result<Status> {
+        if self.tape_count() != 1 {
+            bail!(FastForwardError::MultiTape);
+        }
+        if self.tape_model != TapeModel::TwoWay {
+            bail!(FastForwardError::TapeModel);
+        }
+        if self.seen_configurations.is_some() {
+            bail!(FastForwardError::LoopDetection);
+        }
+        if self.seed.is_some() {
+            bail!(FastForwardError::Probabilistic);
+        }
+        if self.oracle.is_some() {
+            bail!(FastForwardError::Oracle);
+        }
+        if macro_size == 0 {
+            bail!(FastForwardError::MacroSize);
+        }
+        if self.status != Status::Running {
+            return Ok(self.status);
+        }
+
+        let size = macro_size as isize;
+        let tape = &self.tapes[0];
+        let blank = self.blank();
+        let origin = tape.bounds().start;
+        let head_index = (tape.head() - origin) / size;
+        let cell_at = |index: isize| {
+            (0..size)
+                .map(|i| tape.get(origin + index * size + i))
+                .collect::<Cell>()
+        };
+        let mut rle = RunLengthTape {
+            left: vec![],
+            right: vec![],
+            head: cell_at(head_index),
+            index: head_index,
+            blank: vec![blank; macro_size].into(),
+        };
+        for index in 0..head_index {
+            RunLengthTape::push(&mut rle.left, cell_at(index), 1);
+        }
+        let end_index = (tape.bounds().end - origin + size - 1) / size;
+        for index in (head_index + 1..end_index).rev() {
+            RunLengthTape::push(&mut rle.right, cell_at(index), 1);
+        }
+        let mut state = self.current_state;
+        let mut position = (tape.head() - origin) % size;
+        let mut steps = self.steps;
+        // The cells a plain run would have extended the tape over.
+        let mut visited = (tape.bounds().start, tape.bounds().end - 1);
+        let mut visit = |first: isize, last: isize, step: &MacroStep| {
+            visited.0 = visited.0.min(origin + first * size + step.low);
+            visited.1 = visited.1.max(origin + last * size + step.high);
+        };
+
+        let started_at = *self.started_at.get_or_insert_with(Instant::now);
+        let mut cache = HashMap::<(StateId, isize, Cell), MacroStep>::new();
+        let status = loop {
+            if is_limit_exceeded(steps, None, self.timeout, Some(started_at)) {
+                break Status::Timeout;
+            }
+            let remaining = self.max_steps.map(|max| max.saturating_sub(steps));
+
+            // A cached result that needs more steps than remain is simulated again up to the limit.
+            let key = (state, position, rle.head.clone());
+            let step = match cache.get(&key) {
+                Some(step) if remaining.is_none_or(|r| step.steps <= r) => step.clone(),
+                _ => {
+                    let limit = remaining.unwrap_or(usize::MAX).min(CHUNK_STEPS);
+                    let step = simulate(&self.program, state, position, &rle.head, limit);
+                    if step.status != Status::Running || !(0..size).contains(&step.position) {
+                        cache.insert(key, step.clone());
+                    }
+                    step
+                }
+            };
+
+            if step.status != Status::Running {
+                visit(rle.index, rle.index, &step);
+                steps += step.steps;
+                state = step.state;
+                position = step.position;
+                rle.head = step.cell;
+                break step.status;
+            }
+            if (0..size).contains(&step.position) {
+                if step.steps == 0 {
+                    break Status::Timeout;
+                }
+                visit(rle.index, rle.index, &step);
+                steps += step.steps;
+                state = step.state;
+                position = step.position;
+                rle.head = step.cell;
+                continue;
+            }
+
+            let (direction, entry) = if step.position < 0 {
+                (Direction::Left, size - 1)
+            } else {
+                (Direction::Right, 0)
+            };
+            // Leaving the cell the same way it was entered, in the same state, means every equal
+            // cell ahead is handled exactly like this one.
+            let mut count = 1;
+            if step.state == state && entry == position {
+                let run = rle.run_length(direction, &rle.head);
+                let affordable = remaining.map(|r| r / step.steps);
+                count = match (run, affordable) {
+                    (Some(run), Some(affordable)) => (run + 1).min(affordable),
+                    (Some(run), None) => run + 1,
+                    (None, Some(affordable)) => affordable,
+                    (None, None) => ENDLESS_SWEEP_CELLS,
+                };
+            }
+            // Every cell swept over is visited the same way as the current one.
+            let last = count as isize - 1;
+            match direction {
+                Direction::Left => visit(rle.index - last, rle.index, &step),
+                _ => visit(rle.index, rle.index + last, &step),
+            }
+            steps += count * step.steps;
+            state = step.state;
+            position = entry;
+            rle.sweep(direction, step.cell, count);
+        };
+
+        // Only non-blank cells are written, along with both ends of the cells the head has
+        // visited so that the tape covers the same cells as after a plain run. Macro cells can
+        // stick out past those ends, but only with blanks.
+        let mut tape = self.tape_backend.new_tape(&[], blank);
+        tape.set(visited.0, blank);
+        tape.set(visited.1, blank);
+        let mut index = rle.index - rle.left.iter().map(|(_, count)| count).sum::<usize>() as isize;
+        let cells = (rle.left.iter())
+            .map(|(cell, count)| (cell, *count))
+            .chain([(&rle.head, 1)])
+            .chain(rle.right.iter().rev().map(|(cell, count)| (cell, *count)));
+        for (cell, count) in cells {
+            if *cell != rle.blank {
+                for i in index..index + count as isize {
+                    for (j, &symbol) in cell.iter().enumerate() {
+                        if symbol != blank {
+                            tape.set(origin + i * size + j as isize, symbol);
+                        }
+                    }
+                }
+            }
+            index += count as isize;
+        }
+        tape.set_head(origin + rle.index * size + position);
+
+        self.tapes = vec![tape];
+        self.current_state = state;
+        self.steps = steps;
+        self.status = status;
+        self.history = History::starting_at(&self.tapes, steps);
+        self.end_status = None;
+
+        Ok(status)
+    }
+}
+
+// Runs the machine step by step inside `cell`, in the same order of checks as
+// `TuringMachine::next`, until the head leaves the cell, the machine stops or `limit` steps
+// have been taken.
+fn simulate(
+    program: &Program,
+    mut state: StateId,
+    mut position: isize,
+    cell: &[Symbol],
+    limit: usize,
+) -> MacroStep {
+    let mut cell = cell.to_vec();
+    let mut steps = 0;
+    let (mut low, mut high) = (position, position);
+    let status = loop {
+        if program.is_kind(state, StateKind::Reject) {
+            break Status::Reject;
+        }
+        if program.is_kind(state, StateKind::Halt) {
+            break Status::Halt;
+        }
+        let Ok(transition) = program.transition(state, &cell[position as usize..][..1]) else {
+            break if program.is_kind(state, StateKind::Accept) {
+                Status::Accept
+            } else {
+                Status::Stuck
+            };
+        };
+        if steps >= limit {
+            break Status::Running;
+        }
+
+        cell[position as usize] = transition.write[0];
+        match transition.move_head[0] {
+            Direction::Left => position -= 1,
+            Direction::Right => position += 1,
+            _ => {}
+        }
+        state = transition.to;
+        steps += 1;
+        (low, high) = (low.min(position), high.max(position));
+        if !(0..cell.len() as isize).contains(&position) {
+            break Status::Running;
+        }
+    };
+
+    MacroStep {
+        state,
+        position,
+        low,
+        high,
+        cell: cell.into(),
+        steps,
+        status,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TapeBackend, TuringMachineBuilder};
+
+    // The 4-state busy beaver, which halts after 107 steps.
+    fn busy_beaver() -> Result<TuringMachine> {
+        TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C", "D", "H"])
+            .add_transitions(&[
+                ("A", "B", "0", "1", Direction::Right),
+                ("A", "B", "1", "1", Direction::Left),
+                ("B", "A", "0", "1", Direction::Left),
+                ("B", "C", "1", "0", Direction::Left),
+                ("C", "H", "0", "1", Direction::Right),
+                ("C", "D", "1", "1", Direction::Left),
+                ("D", "D", "0", "1", Direction::Right),
+                ("D", "A", "1", "0", Direction::Right),
+            ])?
+            .set_initial_state("A")?
+            .set_accept_states(&["H"])?
+            .set_blank_symbol("0")
+            .build()
+    }
+
+    fn assert_same_run(tm: &TuringMachine, macro_size: usize) -> Result<()> {
+        let mut plain = tm.clone();
+        plain.run();
+        let mut fast = tm.clone();
+        fast.fast_forward(macro_size)?;
+
+        assert_eq!(fast.status, plain.status);
+        assert_eq!(fast.steps, plain.steps);
+        assert_eq!(fast.current_state, plain.current_state);
+        assert_eq!(fast.configuration_hash(), plain.configuration_hash());
+        assert_eq!(fast.tape_contents(0), plain.tape_contents(0));
+        assert_eq!(fast.tapes[0].bounds(), plain.tapes[0].bounds());
+        assert_eq!(fast.tapes[0].head(), plain.tapes[0].head());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fast_forward() -> Result<()> {
+        let tm = busy_beaver()?;
+        for macro_size in 1..=4 {
+            assert_same_run(&tm, macro_size)?;
+            for max_steps in [0, 1, 50, 106, 107, 108] {
+                let mut tm = tm.clone();
+                tm.max_steps = Some(max_steps);
+                assert_same_run(&tm, macro_size)?;
+            }
+        }
+
+        let mut tm = busy_beaver()?;
+        assert_eq!(tm.fast_forward(1)?, Status::Accept);
+        assert_eq!(tm.steps, 107);
+        assert_eq!(
+            tm.tape_contents(0).iter().filter(|&&s| s == "1").count(),
+            13
+        );
+
+        let mut tm = busy_beaver()?;
+        tm.max_steps = Some(107);
+        assert_eq!(tm.fast_forward(2)?, Status::Accept);
+
+        // Fills the blanks up to the input, which starts past the macro cell the head is in.
+        let tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "A", "_", "1", Direction::Right)?
+            .add_transition("A", "B", "1", "1", Direction::Stay)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_tape(vec!["1".to_string(); 2])
+            .set_head(-5)
+            .set_blank_symbol("_")
+            .build()?;
+        for macro_size in 1..=3 {
+            assert_same_run(&tm, macro_size)?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fast_forward_sweep() -> Result<()> {
+        // Turns a long run of 1s into 0s, bounces off the end and walks off into the blanks. The
+        // head ends up far away, which only a sparse tape can hold.
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "A", "1", "0", Direction::Right)?
+            .add_transition("A", "B", "_", "x", Direction::Left)?
+            .add_transition("B", "B", "*", "*", Direction::Left)?
+            .set_initial_state("A")?
+            .set_tape(vec!["1".to_string(); 1000])
+            .set_blank_symbol("_")
+            .set_tape_backend(TapeBackend::Sparse)
+            .set_max_steps(1_000_000_000)
+            .build()?;
+        for macro_size in [1, 3] {
+            let mut fast = tm.clone();
+            assert_eq!(fast.fast_forward(macro_size)?, Status::Timeout);
+            assert_eq!(fast.steps, 1_000_000_000);
+            assert_eq!(fast.tapes[0].head(), 1000 - (1_000_000_000 - 1001) - 1);
+        }
+
+        tm.max_steps = Some(5000);
+        assert_same_run(&tm, 1)?;
+        assert_same_run(&tm, 7)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use crate::{
+    program::Program,
+    state::{Direction, StateId, StateKind},
+    symbol::Symbol,
+    tape::{DequeTape, Tape},
+    turing_machine::{is_limit_exceeded, Status},
+};
+use std::{
+    collections::VecDeque,
+    slice,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GridTape {
+    pub cells: VecDeque<VecDeque<Symbol>>,
+    pub head: (usize, usize),
+    pub origin: (usize, usize),
+    pub heading: Direction,
+}
+impl GridTape {
+    pub fn new(symbols: &[Symbol], blank: Symbol) -> Self {
+        Self {
+            cells: VecDeque::from([VecDeque::from(DequeTape::new(symbols, blank).symbols())]),
+            head: (0, 0),
+            origin: (0, 0),
+            heading: Direction::Up,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.cells[0].len()
+    }
+
+    pub fn height(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn read(&self) -> Symbol {
+        let (x, y) = self.head;
+        self.cells[y][x]
+    }
+
+    pub fn write(&mut self, symbol: Symbol) {
+        let (x, y) = self.head;
+        self.cells[y][x] = symbol;
+    }
+
+    // Growing to the left or up pushes to the front of the rows and moves `origin` along, so the
+    // cells already on the grid are not shifted.
+    pub fn move_head(&mut self, direction: Direction, blank: Symbol) {
+        let direction = match direction {
+            Direction::Stay => return,
+            Direction::Forward => self.heading,
+            Direction::TurnLeft => turn_left(self.heading),
+            Direction::TurnRight => turn_right(self.heading),
+            Direction::UTurn => turn_right(turn_right(self.heading)),
+            direction => direction,
+        };
+        self.heading = direction;
+
+        let (x, y) = &mut self.head;
+        match direction {
+            Direction::Left => {
+                if *x == 0 {
+                    for row in &mut self.cells {
+                        row.push_front(blank);
+                    }
+                    self.origin.0 += 1;
+                } else {
+                    *x -= 1;
+                }
+            }
+            Direction::Right => {
+                *x += 1;
+                if *x == self.cells[0].len() {
+                    for row in &mut self.cells {
+                        row.push_back(blank);
+                    }
+                }
+            }
+            Direction::Up => {
+                if *y == 0 {
+                    let width = self.cells[0].len();
+                    self.cells.push_front(VecDeque::from(vec![blank; width]));
+                    self.origin.1 += 1;
+                } else {
+                    *y -= 1;
+                }
+            }
+            Direction::Down => {
+                *y += 1;
+                if *y == self.cells.len() {
+                    let width = self.cells[0].len();
+                    self.cells.push_back(VecDeque::from(vec![blank; width]));
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn turn_left(heading: Direction) -> Direction {
+    match heading {
+        Direction::Up => Direction::Left,
+        Direction::Left => Direction::Down,
+        Direction::Down => Direction::Right,
+        _ => Direction::Up,
+    }
+}
+
+fn turn_right(heading: Direction) -> Direction {
+    match heading {
+        Direction::Up => Direction::Right,
+        Direction::Right => Direction::Down,
+        Direction::Down => Direction::Left,
+        _ => Direction::Up,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GridSnapshot {
+    pub current_state: StateId,
+    pub next_state: StateId,
+    pub grid: GridTape,
+    pub read: Symbol,
+    pub write: Symbol,
+    pub status: Status,
+}
+
+#[derive(Debug, Clone)]
+pub struct GridTuringMachine {
+    pub program: Arc<Program>,
+    pub current_state: StateId,
+    pub grid: GridTape,
+    pub status: Status,
+    pub snapshots: Vec<GridSnapshot>,
+    pub steps: usize,
+    pub max_steps: Option<usize>,
+    pub timeout: Option<Duration>,
+    started_at: Option<Instant>,
+}
+impl GridTuringMachine {
+    pub fn new(program: Arc<Program>, tape: &[Symbol]) -> Self {
+        Self {
+            current_state: program.initial_state(),
+            grid: GridTape::new(tape, program.blank()),
+            program,
+            status: Status::Running,
+            snapshots: Vec::new(),
+            steps: 0,
+            max_steps: None,
+            timeout: None,
+            started_at: None,
+        }
+    }
+
+    pub fn blank(&self) -> Symbol {
+        self.program.blank()
+    }
+
+    pub fn grid_contents(&self) -> Vec<Vec<&str>> {
+        let alphabet = self.program.alphabet();
+        self.grid
+            .cells
+            .iter()
+            .map(|row| row.iter().map(|&s| alphabet.name(s)).collect())
+            .collect()
+    }
+
+    pub fn run(&mut self) -> Status {
+        for _ in self.by_ref() {}
+
+        self.status
+    }
+
+    pub fn run_with_limit(
+        &mut self,
+        max_steps: Option<usize>,
+        timeout: Option<Duration>,
+    ) -> Status {
+        self.max_steps = max_steps;
+        self.timeout = timeout;
+        self.started_at = None;
+
+        self.run()
+    }
+
+    fn is_current_state(&self, kind: StateKind) -> bool {
+        self.program.is_kind(self.current_state, kind)
+    }
+}
+impl Iterator for GridTuringMachine {
+    type Item = StateId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.status != Status::Running {
+            return None;
+        }
+        if self.is_current_state(StateKind::Reject) {
+            self.status = Status::Reject;
+            return None;
+        }
+        if self.is_current_state(StateKind::Halt) {
+            self.status = Status::Halt;
+            return None;
+        }
+
+        let read = self.grid.read();
+        let transition = (self.program).transition(self.current_state, slice::from_ref(&read));
+        let Ok(transition) = transition else {
+            self.status = if self.is_current_state(StateKind::Accept) {
+                Status::Accept
+            } else {
+                Status::Stuck
+            };
+            return None;
+        };
+        self.started_at.get_or_insert_with(Instant::now);
+        if is_limit_exceeded(self.steps, self.max_steps, self.timeout, self.started_at) {
+            self.status = Status::Timeout;
+            return None;
+        }
+        self.snapshots.push(GridSnapshot {
+            current_state: self.current_state,
+            next_state: transition.to,
+            grid: self.grid.clone(),
+            read,
+            write: transition.write[0],
+            status: self.status,
+        });
+
+        self.current_state = transition.to;
+        self.grid.write(transition.write[0]);
+        self.grid
+            .move_head(transition.move_head[0], self.program.blank());
+        self.steps += 1;
+
+        Some(self.current_state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TuringMachineBuilder;
+    use anyhow::Result;
+
+    #[test]
+    fn test_langtons_ant() -> Result<()> {
+        // Turns right on a blank and left on a #, flipping the cell it leaves.
+        let builder = TuringMachineBuilder::new()
+            .add_state("ant")
+            .add_transition("ant", "ant", "_", "#", Direction::TurnRight)?
+            .add_transition("ant", "ant", "#", "_", Direction::TurnLeft)?
+            .set_initial_state("ant")?
+            .set_tape(vec!["_".to_string()])
+            .set_blank_symbol("_");
+
+        let mut tm = builder.clone().build_grid()?;
+        assert_eq!(tm.run_with_limit(Some(4), None), Status::Timeout);
+        assert_eq!(tm.grid_contents(), vec![vec!["#", "#"], vec!["#", "#"]]);
+        assert_eq!(tm.grid.head, (0, 0));
+        assert_eq!(tm.grid.heading, Direction::Up);
+
+        let mut tm = builder.clone().build_grid()?;
+        assert_eq!(tm.run_with_limit(Some(5), None), Status::Timeout);
+        assert_eq!(
+            tm.grid_contents(),
+            vec![vec!["_", "_", "#"], vec!["_", "#", "#"]]
+        );
+        assert_eq!(tm.grid.head, (0, 0));
+        assert_eq!(tm.grid.origin, (1, 0));
+        assert_eq!(tm.grid.heading, Direction::Left);
+
+        Ok(())
+    }
+}
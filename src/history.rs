@@ -0,0 +1,241 @@
+use crate::{
+    state::StateId,
+    symbol::Symbol,
+    tape::Tape,
+    turing_machine::{Snapshot, Status},
+};
+use thiserror::Error;
+
+const KEYFRAME_INTERVAL: usize = 1024;
+
+#[derive(Debug, Error)]
+#[error("Step {0} is not in the history")]
+pub struct NotInHistory(pub usize);
+
+// What a single step changed. Each tape had `write` written under its head, which then moved
+// from the first to the second position in `head_moves`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub current_state: StateId,
+    pub next_state: StateId,
+    pub read: Vec<Symbol>,
+    pub write: Vec<Symbol>,
+    pub head_moves: Vec<(isize, isize)>,
+    pub status: Status,
+}
+impl Step {
+    pub fn apply(&self, tapes: &mut [Box<dyn Tape>]) {
+        let changes = self.write.iter().zip(&self.head_moves);
+        for (tape, (&write, &(from, to))) in tapes.iter_mut().zip(changes) {
+            tape.set(from, write);
+            tape.set_head(to);
+        }
+    }
+}
+
+// The execution history of a machine. Only the changes made by each step are recorded, along
+// with the full tapes every `keyframe_interval` steps, so any step can be rebuilt by replaying
+// at most `keyframe_interval` steps from the nearest keyframe. Steps are numbered from
+// `first_step`, which is not zero for a machine resumed from a checkpoint.
+#[derive(Debug, Clone)]
+pub struct History {
+    first_step: usize,
+    steps: Vec<Step>,
+    keyframes: Vec<Vec<Box<dyn Tape>>>,
+    keyframe_interval: usize,
+}
+impl History {
+    pub fn new(tapes: &[Box<dyn Tape>]) -> Self {
+        Self::starting_at(tapes, 0)
+    }
+
+    pub fn starting_at(tapes: &[Box<dyn Tape>], first_step: usize) -> Self {
+        Self {
+            first_step,
+            ..Self::with_keyframe_interval(tapes, KEYFRAME_INTERVAL)
+        }
+    }
+
+    pub fn with_keyframe_interval(tapes: &[Box<dyn Tape>], keyframe_interval: usize) -> Self {
+        Self {
+            first_step: 0,
+            steps: vec![],
+            keyframes: vec![tapes.to_vec()],
+            keyframe_interval: keyframe_interval.max(1),
+        }
+    }
+
+    // `tapes` are the tapes after `step` has been applied.
+    pub fn push(&mut self, step: Step, tapes: &[Box<dyn Tape>]) {
+        self.steps.push(step);
+        if self.steps.len().is_multiple_of(self.keyframe_interval) {
+            self.keyframes.push(tapes.to_vec());
+        }
+    }
+
+    // Forgets every step from `end` on.
+    pub fn truncate(&mut self, end: usize) {
+        let len = end.saturating_sub(self.first_step);
+        self.steps.truncate(len);
+        self.keyframes.truncate(len / self.keyframe_interval + 1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn first_step(&self) -> usize {
+        self.first_step
+    }
+
+    // The number of the step after the last recorded one.
+    pub fn end(&self) -> usize {
+        self.first_step + self.steps.len()
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn step(&self, step: usize) -> Option<&Step> {
+        self.steps.get(step.checked_sub(self.first_step)?)
+    }
+
+    pub fn initial_tapes(&self) -> &[Box<dyn Tape>] {
+        &self.keyframes[0]
+    }
+
+    // Rebuilds the tapes as they were right before step `step`.
+    pub fn tapes(&self, step: usize) -> Option<Vec<Box<dyn Tape>>> {
+        let index = step.checked_sub(self.first_step)?;
+        if index > self.steps.len() {
+            return None;
+        }
+
+        let keyframe = index / self.keyframe_interval;
+        let mut tapes = self.keyframes[keyframe].clone();
+        for step in &self.steps[keyframe * self.keyframe_interval..index] {
+            step.apply(&mut tapes);
+        }
+
+        Some(tapes)
+    }
+
+    pub fn get(&self, step: usize) -> Option<Snapshot> {
+        let tapes = self.tapes(step)?;
+
+        Some(snapshot(self.step(step)?, tapes))
+    }
+
+    // Replays the whole history from the start, which is cheaper than calling `get` for each step.
+    pub fn iter(&self) -> impl Iterator<Item = Snapshot> + '_ {
+        let mut tapes = self.keyframes[0].clone();
+        self.steps.iter().map(move |step| {
+            let snapshot = snapshot(step, tapes.clone());
+            step.apply(&mut tapes);
+            snapshot
+        })
+    }
+}
+
+fn snapshot(step: &Step, tapes: Vec<Box<dyn Tape>>) -> Snapshot {
+    Snapshot {
+        current_state: step.current_state,
+        next_state: step.next_state,
+        tapes,
+        read: step.read.clone(),
+        write: step.write.clone(),
+        status: step.status,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Direction, TuringMachineBuilder};
+    use anyhow::Result;
+
+    #[test]
+    fn test_history() -> Result<()> {
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "A", "1", "0", Direction::Right)?
+            .add_transition("A", "B", "_", "_", Direction::Left)?
+            .add_transition("B", "B", "0", "1", Direction::Left)?
+            .set_initial_state("A")?
+            .set_tape(vec!["1".to_string(); 5])
+            .set_blank_symbol("_")
+            .build()?;
+        let initial_tapes = tm.tapes.clone();
+        tm.run();
+
+        let mut tapes = initial_tapes.clone();
+        let mut keyframed = History::with_keyframe_interval(&initial_tapes, 3);
+        for step in tm.history.steps() {
+            step.apply(&mut tapes);
+            keyframed.push(step.clone(), &tapes);
+        }
+
+        assert_eq!(tm.history.len(), 11);
+        let expected = tm.history.iter().collect::<Vec<_>>();
+        for (i, snapshot) in expected.iter().enumerate() {
+            let rebuilt = keyframed.get(i).unwrap();
+            assert_eq!(rebuilt.tapes[0].symbols(), snapshot.tapes[0].symbols());
+            assert_eq!(rebuilt.tapes[0].head(), snapshot.tapes[0].head());
+            assert_eq!(rebuilt.current_state, snapshot.current_state);
+        }
+        assert!(keyframed.get(11).is_none());
+        assert_eq!(
+            keyframed.tapes(11).unwrap()[0].symbols(),
+            tm.tapes[0].symbols()
+        );
+        assert_eq!(keyframed.tapes(11).unwrap()[0].head(), tm.tapes[0].head());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_seek() -> Result<()> {
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C"])
+            .add_transition("A", "A", "1", "0", Direction::Left)?
+            .add_transition("A", "B", "_", "1", Direction::Right)?
+            .add_transition("B", "C", "0", "0", Direction::Stay)?
+            .set_initial_state("A")?
+            .set_accept_states(&["C"])?
+            .set_tape(vec!["1".to_string()])
+            .set_blank_symbol("_")
+            .enable_loop_detection()
+            .build()?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.steps, 3);
+
+        tm.seek(1)?;
+        assert_eq!(tm.current_state_name(), "A");
+        assert_eq!(tm.status, Status::Running);
+        assert_eq!(tm.steps, 1);
+        assert_eq!(tm.tape_contents(0), vec!["_", "0"]);
+        assert_eq!(tm.tapes[0].head(), -1);
+
+        assert!(tm.step_back().is_some());
+        assert_eq!(tm.tape_contents(0), vec!["1"]);
+        assert_eq!(tm.tapes[0].bounds(), 0..1);
+        assert!(tm.step_back().is_none());
+        assert!(tm.seek(4).is_err());
+
+        tm.seek(3)?;
+        assert_eq!(tm.current_state_name(), "C");
+        assert_eq!(tm.status, Status::Accept);
+
+        tm.seek(1)?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.history.len(), 3);
+        assert_eq!(tm.tape_contents(0), vec!["1", "0"]);
+
+        Ok(())
+    }
+}
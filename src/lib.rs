@@ -1,9 +1,34 @@
+mod breakpoint;
 mod builder;
+mod checkpoint;
+mod compose;
 mod config;
+mod fast_forward;
+mod grid;
+mod history;
+mod nondeterministic;
+mod observer;
+mod oracle;
+mod probabilistic;
+mod program;
 mod state;
+mod symbol;
+mod tape;
 mod turing_machine;
 
+pub use breakpoint::{Breakpoint, InvalidBreakpoint};
 pub use builder::TuringMachineBuilder;
-pub use config::Config;
-pub use state::{Direction, State, Transition};
-pub use turing_machine::{Status, TuringMachine};
+pub use checkpoint::{Checkpoint, CheckpointError, TapeCheckpoint};
+pub use compose::ComposeError;
+pub use config::{Config, ConfigError, ConfigMachine, ConfigOracle, ConfigTransition, OneOrMany};
+pub use fast_forward::FastForwardError;
+pub use grid::{GridSnapshot, GridTape, GridTuringMachine};
+pub use history::{History, NotInHistory, Step};
+pub use observer::{StepEvent, StepObserver};
+pub use oracle::{Oracle, OracleLink};
+pub use probabilistic::MonteCarlo;
+pub use program::Program;
+pub use state::{Direction, State, StateId, StateKind, Transition};
+pub use symbol::{Alphabet, Symbol};
+pub use tape::{DequeTape, OutputMode, SparseTape, Tape, TapeBackend, TapeModel};
+pub use turing_machine::{Snapshot, Status, TuringMachine};
@@ -0,0 +1,211 @@
+use crate::{
+    history::Step,
+    state::{Direction, StateId, StateKind, Transition},
+    symbol::Symbol,
+    tape::{read_tapes, Tape},
+    turing_machine::{Configuration, Status, TuringMachine},
+};
+use std::{borrow::Cow, collections::HashSet, sync::Arc, time::Instant};
+
+struct Branch {
+    state: StateId,
+    tapes: Vec<Box<dyn Tape>>,
+    node: Option<usize>,
+}
+
+struct Node {
+    parent: Option<usize>,
+    step: Step,
+}
+
+impl TuringMachine {
+    // Explores every branch breadth-first. When a branch accepts, the machine is moved to the
+    // accepting configuration and `history` holds the path to it. If every branch dies without
+    // accepting the run is rejected, and if the frontier grows beyond `max_frontier` it stops
+    // with `FrontierExceeded`.
+    pub fn run_nondeterministic(&mut self, max_frontier: usize) -> Status {
+        if self.status != Status::Running {
+            return self.status;
+        }
+        self.started_at.get_or_insert_with(Instant::now);
+
+        let root_tapes = self.tapes.clone();
+        let root = Branch {
+            state: self.current_state,
+            tapes: root_tapes.clone(),
+            node: None,
+        };
+        let mut seen = HashSet::from([self.configuration()]);
+        let mut nodes: Vec<Node> = vec![];
+        let mut frontier = vec![root];
+        loop {
+            if frontier.is_empty() {
+                self.status = Status::Reject;
+                return self.status;
+            }
+            if let Some(branch) = frontier.iter().find(|b| self.is_accepting(b)) {
+                let mut path = vec![];
+                let mut node = branch.node;
+                while let Some(id) = node {
+                    path.push(&nodes[id].step);
+                    node = nodes[id].parent;
+                }
+
+                let mut tapes = root_tapes;
+                for step in path.into_iter().rev() {
+                    step.apply(&mut tapes);
+                    self.history.push(step.clone(), &tapes);
+                }
+                self.current_state = branch.state;
+                self.tapes = tapes;
+                self.status = Status::Accept;
+                return self.status;
+            }
+            if self.is_limit_exceeded() {
+                self.status = Status::Timeout;
+                return self.status;
+            }
+            if frontier.len() > max_frontier {
+                self.status = Status::FrontierExceeded;
+                return self.status;
+            }
+
+            let program = Arc::clone(&self.program);
+            let mut next_frontier = vec![];
+            for branch in frontier {
+                if program.is_kind(branch.state, StateKind::Reject)
+                    || program.is_kind(branch.state, StateKind::Halt)
+                {
+                    continue;
+                }
+
+                let read = read_tapes(&branch.tapes);
+                for transition in self.branch_transitions(&branch, &read).iter() {
+                    let is_out_of_bounds =
+                        branch
+                            .tapes
+                            .iter()
+                            .zip(&transition.move_head)
+                            .any(|(tape, &direction)| {
+                                tape.check_move(direction, self.tape_model).is_err()
+                            });
+                    if is_out_of_bounds {
+                        continue;
+                    }
+
+                    let mut tapes = branch.tapes.clone();
+                    let mut head_moves = vec![];
+                    let moves = transition.write.iter().zip(&transition.move_head);
+                    for (tape, (write, &direction)) in tapes.iter_mut().zip(moves) {
+                        let head = tape.head();
+                        tape.write(*write);
+                        tape.move_head(direction, self.tape_model);
+                        head_moves.push((head, tape.head()));
+                    }
+
+                    if !seen.insert(Configuration::new(transition.to, &tapes)) {
+                        continue;
+                    }
+
+                    nodes.push(Node {
+                        parent: branch.node,
+                        step: Step {
+                            current_state: branch.state,
+                            next_state: transition.to,
+                            read: read.clone(),
+                            write: transition.write.clone(),
+                            head_moves,
+                            status: Status::Running,
+                        },
+                    });
+                    next_frontier.push(Branch {
+                        state: transition.to,
+                        tapes,
+                        node: Some(nodes.len() - 1),
+                    });
+                }
+            }
+            frontier = next_frontier;
+            self.steps += 1;
+        }
+    }
+
+    // A branch in the query state of an oracle only follows the answer, as in `take_step`.
+    fn branch_transitions(&self, branch: &Branch, read: &[Symbol]) -> Cow<'_, [Transition]> {
+        match self.oracle_answer_at(branch.state, &branch.tapes) {
+            Some(state) => {
+                let stay = vec![Direction::Stay; read.len()];
+                let answer = Transition::new_multi_tape(branch.state, state, read, read, &stay);
+                Cow::Owned(vec![answer])
+            }
+            None => self.program.transitions(branch.state, read),
+        }
+    }
+
+    fn is_accepting(&self, branch: &Branch) -> bool {
+        self.program.is_kind(branch.state, StateKind::Accept)
+            && (self.branch_transitions(branch, &read_tapes(&branch.tapes))).is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Direction, Status, TuringMachineBuilder};
+    use anyhow::Result;
+
+    #[test]
+    fn test_run_nondeterministic() -> Result<()> {
+        // On the 0 either keeps it and gets stuck, or writes a 1 and accepts on the blank after it.
+        let builder = TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C", "D"])
+            .add_transition("A", "B", "0", "0", Direction::Right)?
+            .add_transition("A", "C", "0", "1", Direction::Right)?
+            .add_transition("C", "C", "0", "0", Direction::Left)?
+            .add_transition("C", "D", "_", "_", Direction::Left)?
+            .set_initial_state("A")?
+            .set_accept_states(&["D"])?
+            .set_tape(vec!["0".to_string()])
+            .set_blank_symbol("_");
+
+        let mut tm = builder.clone().build()?;
+        assert_eq!(tm.run(), Status::Stuck);
+
+        let mut tm = builder.clone().build()?;
+        assert_eq!(tm.run_nondeterministic(16), Status::Accept);
+        assert_eq!(tm.steps, 2);
+        assert_eq!(
+            tm.history
+                .steps()
+                .iter()
+                .map(|s| {
+                    (
+                        tm.program.state(s.current_state).name.as_str(),
+                        tm.program.state(s.next_state).name.as_str(),
+                    )
+                })
+                .collect::<Vec<_>>(),
+            vec![("A", "C"), ("C", "D")]
+        );
+        assert_eq!(tm.tape_contents(0), vec!["1", "_"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_nondeterministic_frontier_limit() -> Result<()> {
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "A", "_", "0", Direction::Right)?
+            .add_transition("A", "A", "_", "1", Direction::Right)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_tape(vec!["_".to_string()])
+            .set_blank_symbol("_")
+            .build()?;
+
+        assert_eq!(tm.run_nondeterministic(8), Status::FrontierExceeded);
+        assert_eq!(tm.steps, 4);
+
+        Ok(())
+    }
+}
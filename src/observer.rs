@@ -0,0 +1,105 @@
+use crate::{
+    history::{History, Step},
+    program::Program,
+    tape::Tape,
+    turing_machine::TuringMachine,
+};
+
+// A step that has just been taken. `number` counts from 0, and `tapes` are the tapes after the
+// step.
+#[derive(Debug, Clone, Copy)]
+pub struct StepEvent<'a> {
+    pub number: usize,
+    pub step: &'a Step,
+    pub tapes: &'a [Box<dyn Tape>],
+    pub program: &'a Program,
+}
+
+// Receives every step a machine takes while it runs. `on_halt` is called once, when the machine
+// stops with a status other than `Running`.
+pub trait StepObserver {
+    fn on_step(&mut self, event: &StepEvent);
+
+    fn on_halt(&mut self, _tm: &TuringMachine) {}
+}
+
+// The history of a machine is itself an observer, which `TuringMachine::next` feeds unless
+// `record_history` is turned off.
+impl StepObserver for History {
+    fn on_step(&mut self, event: &StepEvent) {
+        self.truncate(event.number);
+        self.push(event.step.clone(), event.tapes);
+    }
+}
+
+impl<F: FnMut(&StepEvent)> StepObserver for F {
+    fn on_step(&mut self, event: &StepEvent) {
+        self(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Direction, Status, TuringMachineBuilder};
+    use anyhow::Result;
+
+    #[derive(Default)]
+    struct Statistics {
+        steps: usize,
+        writes: usize,
+        status: Option<Status>,
+    }
+    impl StepObserver for Statistics {
+        fn on_step(&mut self, event: &StepEvent) {
+            self.steps += 1;
+            self.writes += (event.step.read != event.step.write) as usize;
+        }
+
+        fn on_halt(&mut self, tm: &TuringMachine) {
+            assert!(self.status.is_none());
+            self.status = Some(tm.status);
+        }
+    }
+
+    #[test]
+    fn test_observer() -> Result<()> {
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "A", "1", "0", Direction::Right)?
+            .add_transition("A", "A", "0", "0", Direction::Right)?
+            .add_transition("A", "B", "_", "_", Direction::Left)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_tape(["1", "0", "1"].map(String::from).to_vec())
+            .set_blank_symbol("_")
+            .disable_history()
+            .build()?;
+
+        let mut statistics = Statistics::default();
+        assert_eq!(tm.run_with_observer(&mut statistics), Status::Accept);
+        assert_eq!(statistics.steps, 4);
+        assert_eq!(statistics.writes, 2);
+        assert_eq!(statistics.status, Some(Status::Accept));
+        assert!(tm.history.is_empty());
+
+        assert!(tm.seek(0).is_err());
+        assert_eq!(tm.current_state_name(), "B");
+
+        let mut tm = TuringMachineBuilder::new()
+            .add_states(&["A"])
+            .add_transition("A", "A", "1", "1", Direction::Right)?
+            .set_initial_state("A")?
+            .set_tape(vec!["1".to_string(); 3])
+            .set_blank_symbol("_")
+            .build()?;
+        let mut states = vec![];
+        tm.run_with_observer(&mut |event: &StepEvent| {
+            states.push(event.program.state(event.step.next_state).name.clone())
+        });
+        assert_eq!(states, vec!["A"; 3]);
+        assert_eq!(tm.history.len(), 3);
+
+        Ok(())
+    }
+}
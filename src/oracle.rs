@@ -0,0 +1,135 @@
+use crate::{
+    state::StateId,
+    tape::{OutputMode, Tape},
+    turing_machine::TuringMachine,
+};
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+};
+
+// Answers the queries of an oracle machine. A query is given as the names of the symbols on the
+// query tape, so symbols with longer names are kept apart.
+pub trait Oracle: Send + Sync {
+    fn query(&self, query: &[&str]) -> bool;
+}
+
+impl<F: Fn(&[&str]) -> bool + Send + Sync> Oracle for F {
+    fn query(&self, query: &[&str]) -> bool {
+        self(query)
+    }
+}
+
+// Connects a machine to an oracle. Once the machine enters `query_state`, its next step asks the
+// oracle about the query on `tape`, read the way `TuringMachine::output` reads `region`, and moves
+// to `yes_state` or `no_state` without touching the tapes.
+#[derive(Clone)]
+pub struct OracleLink {
+    pub oracle: Arc<dyn Oracle>,
+    pub query_state: StateId,
+    pub yes_state: StateId,
+    pub no_state: StateId,
+    pub tape: usize,
+    pub region: OutputMode,
+}
+impl Debug for OracleLink {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("OracleLink")
+            .field("query_state", &self.query_state)
+            .field("yes_state", &self.yes_state)
+            .field("no_state", &self.no_state)
+            .field("tape", &self.tape)
+            .field("region", &self.region)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TuringMachine {
+    // The state the oracle sends the machine to, if the machine is in the query state.
+    pub(crate) fn oracle_answer(&self) -> Option<StateId> {
+        self.oracle_answer_at(self.current_state, &self.tapes)
+    }
+
+    // The same for a machine in `state` with `tapes`, such as a branch of a nondeterministic run.
+    pub(crate) fn oracle_answer_at(
+        &self,
+        state: StateId,
+        tapes: &[Box<dyn Tape>],
+    ) -> Option<StateId> {
+        let link = (self.oracle.as_ref()).filter(|link| link.query_state == state)?;
+        let query = self.tape_output(tapes[link.tape].as_ref(), link.region);
+
+        Some(if link.oracle.query(&query) {
+            link.yes_state
+        } else {
+            link.no_state
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Direction, Status, TuringMachineBuilder};
+    use anyhow::Result;
+
+    // Copies the input to the second tape one symbol at a time, asking the oracle after every
+    // symbol whether the copy so far is allowed, and accepts if the whole input is.
+    fn prefixes(input: &str, oracle: impl Oracle + 'static) -> Result<TuringMachine> {
+        TuringMachineBuilder::new()
+            .add_states(&["A", "Q", "Y", "N", "B"])
+            .add_multi_tape_transition("A", "Q", &["0", "_"], &["0", "0"], &[Direction::Right; 2])?
+            .add_multi_tape_transition("A", "Q", &["1", "_"], &["1", "1"], &[Direction::Right; 2])?
+            .add_multi_tape_transition("A", "B", &["_", "_"], &["_", "_"], &[Direction::Stay; 2])?
+            .add_multi_tape_transition("Y", "A", &["*", "*"], &["*", "*"], &[Direction::Stay; 2])?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_reject_states(&["N"])?
+            .set_tape(input.chars().map(String::from).collect())
+            .set_tape_count(2)
+            .set_blank_symbol("_")
+            .set_oracle(oracle, "Q", "Y", "N")?
+            .set_query_tape(1, OutputMode::Trim)
+            .build()
+    }
+
+    #[test]
+    fn test_oracle() -> Result<()> {
+        let no_11 = |query: &[&str]| !query.concat().contains("11");
+        let mut tm = prefixes("1010", no_11)?;
+        assert_eq!(tm.run(), Status::Accept);
+        assert_eq!(tm.steps, 13);
+        assert_eq!(tm.tape_contents(1), vec!["1", "0", "1", "0", "_"]);
+
+        let mut tm = prefixes("1011", no_11)?;
+        assert_eq!(tm.run(), Status::Reject);
+        assert_eq!(tm.current_state_name(), "N");
+        let step = tm.history.step(tm.steps - 1).unwrap();
+        assert_eq!(tm.program.state(step.current_state).name, "Q");
+        assert_eq!(step.read, step.write);
+
+        tm.seek(tm.steps - 1)?;
+        assert_eq!(tm.current_state_name(), "Q");
+
+        // Every branch of a nondeterministic run asks the oracle the same way.
+        for input in ["1010", "1011"] {
+            let status = prefixes(input, no_11)?.run();
+            let mut tm = prefixes(input, no_11)?;
+            assert_eq!(tm.run_nondeterministic(10), status);
+            if status == Status::Accept {
+                assert_eq!(tm.tape_contents(1), vec!["1", "0", "1", "0", "_"]);
+            }
+        }
+
+        assert!(TuringMachineBuilder::new()
+            .add_states(&["Q", "Y", "N"])
+            .set_initial_state("Q")?
+            .set_blank_symbol("_")
+            .set_oracle(no_11, "Q", "Y", "N")?
+            .set_query_tape(1, OutputMode::Trim)
+            .build()
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    program::Program,
+    state::{StateId, Stuck, Transition},
+    symbol::Symbol,
+    turing_machine::{Status, TuringMachine},
+};
+use std::{borrow::Cow, collections::BTreeMap};
+
+const GOLDEN_GAMMA: u64 = 0x9e3779b97f4a7c15;
+
+// The `index`-th number of the SplitMix64 sequence for `seed`. Each step draws the number for its
+// own step count rather than the next one of a shared generator, so seeking back and running
+// again, or resuming from a checkpoint, takes the same transitions.
+fn random(seed: u64, index: u64) -> u64 {
+    let mix = |mut z: u64| {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+
+    mix(mix(seed).wrapping_add(index.wrapping_add(1).wrapping_mul(GOLDEN_GAMMA)))
+}
+
+impl Program {
+    // Chooses one of the transitions that apply with probability proportional to its weight.
+    pub fn random_transition(
+        &self,
+        state: StateId,
+        read: &[Symbol],
+        seed: u64,
+        step: usize,
+    ) -> Result<Cow<'_, Transition>, Stuck> {
+        let transitions = self.transitions(state, read);
+        let total = transitions.iter().map(|t| t.weight as u64).sum::<u64>();
+        let mut point = ((random(seed, step as u64) as u128 * total as u128) >> 64) as u64;
+        let chosen = transitions.iter().position(|transition| {
+            match point.checked_sub(transition.weight as u64) {
+                Some(rest) => {
+                    point = rest;
+                    false
+                }
+                None => true,
+            }
+        });
+
+        match (transitions, chosen.ok_or(Stuck)?) {
+            (Cow::Borrowed(transitions), i) => Ok(Cow::Borrowed(&transitions[i])),
+            (Cow::Owned(mut transitions), i) => Ok(Cow::Owned(transitions.swap_remove(i))),
+        }
+    }
+}
+
+// The outcome of running a probabilistic machine many times, with the number of runs that ended
+// with each status.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MonteCarlo {
+    pub trials: usize,
+    pub statuses: BTreeMap<Status, usize>,
+}
+impl MonteCarlo {
+    pub fn count(&self, status: Status) -> usize {
+        self.statuses.get(&status).copied().unwrap_or(0)
+    }
+
+    pub fn accept_probability(&self) -> f64 {
+        self.count(Status::Accept) as f64 / self.trials as f64
+    }
+
+    // The Wilson score interval of the accept probability, where `z` is the quantile of the
+    // standard normal distribution for the confidence level, e.g. 1.96 for 95%. Unlike the normal
+    // approximation it stays within [0, 1] and is still useful when no trial or every trial
+    // accepts.
+    pub fn confidence_interval(&self, z: f64) -> (f64, f64) {
+        let n = self.trials as f64;
+        let p = self.accept_probability();
+        let denominator = 1.0 + z * z / n;
+        let center = (p + z * z / (2.0 * n)) / denominator;
+        let margin = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt() / denominator;
+
+        ((center - margin).max(0.0), (center + margin).min(1.0))
+    }
+}
+
+impl TuringMachine {
+    // Runs copies of the machine `trials` times, each with its own seed derived from `seed`.
+    // Every run is subject to the step and time limits of the machine.
+    pub fn monte_carlo(&self, trials: usize, seed: u64) -> MonteCarlo {
+        let mut result = MonteCarlo {
+            trials,
+            ..Default::default()
+        };
+        for trial in 0..trials {
+            let mut tm = self.clone();
+            tm.seed = Some(random(seed, trial as u64));
+            tm.record_history = false;
+            let status = tm.run();
+            *result.statuses.entry(status).or_default() += 1;
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Direction, TuringMachineBuilder};
+    use anyhow::Result;
+
+    // Flips a coin weighted 3 to 1 for heads at every cell of the input, accepting on the blank
+    // after it and rejecting on the first tail.
+    fn coins(tape_len: usize) -> Result<TuringMachine> {
+        TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C"])
+            .add_weighted_transition("A", "A", "1", "h", Direction::Right, 3)?
+            .add_transition("A", "C", "1", "t", Direction::Stay)?
+            .add_transition("A", "B", "_", "_", Direction::Left)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_reject_states(&["C"])?
+            .set_tape(vec!["1".to_string(); tape_len])
+            .set_blank_symbol("_")
+            .build()
+    }
+
+    #[test]
+    fn test_random_transition() -> Result<()> {
+        let mut tm = coins(64)?;
+        assert_eq!(tm.clone().run(), Status::Accept);
+
+        tm.seed = Some(7);
+        let status = tm.run();
+        let tape = tm.tape_contents(0);
+        assert_eq!(status, Status::Reject);
+        assert!(tape.contains(&"t"));
+
+        // The same seed takes the same transitions, also when going back and running again.
+        let mut again = coins(64)?;
+        again.seed = Some(7);
+        assert_eq!(again.run(), status);
+        assert_eq!(again.tape_contents(0), tape);
+        again.seek(again.steps / 2)?;
+        again.status = Status::Running;
+        assert_eq!(again.run(), status);
+        assert_eq!(again.tape_contents(0), tape);
+
+        let program = &tm.program;
+        let (a, one) = (program.state_id("A").unwrap(), program.symbol("1").unwrap());
+        let heads = (0..4000)
+            .filter(|&step| {
+                let transition = program.random_transition(a, &[one], 1, step).unwrap();
+                program.state(transition.to).name == "A"
+            })
+            .count();
+        assert!((2850..3150).contains(&heads));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_monte_carlo() -> Result<()> {
+        // Two heads in a row come up with probability 9/16.
+        let result = coins(2)?.monte_carlo(2000, 42);
+        assert_eq!(result.trials, 2000);
+        assert_eq!(
+            result.count(Status::Accept) + result.count(Status::Reject),
+            2000
+        );
+        let (low, high) = result.confidence_interval(1.96);
+        assert!(low < result.accept_probability() && result.accept_probability() < high);
+        assert!(low < 0.5625 && 0.5625 < high);
+        assert_eq!(coins(2)?.monte_carlo(2000, 42), result);
+
+        let result = coins(0)?.monte_carlo(10, 0);
+        assert_eq!(result.accept_probability(), 1.0);
+        let (low, high) = result.confidence_interval(1.96);
+        assert!(low > 0.6 && high == 1.0);
+
+        assert!(TuringMachineBuilder::new()
+            .add_state("A")
+            .add_weighted_transition("A", "A", "1", "1", Direction::Right, 0)?
+            .set_initial_state("A")?
+            .set_blank_symbol("_")
+            .build()
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+use crate::{
+    state::{State, StateId, StateKind, Stuck, Transition},
+    symbol::{Alphabet, Symbol},
+};
+use std::{borrow::Cow, collections::HashMap};
+
+// How the transitions for (state, symbols read) are found. A single-tape machine has a dense
+// table with an entry for every symbol, and wildcard transitions are expanded into one transition
+// per symbol they match. With more tapes the number of combinations grows exponentially, so only
+// the transitions without wildcards are indexed by the exact symbols they read, and the ones with
+// wildcards are matched when they are looked up.
+#[derive(Debug, Clone)]
+enum Index {
+    Dense(Vec<(usize, usize)>),
+    Hashed {
+        exact: HashMap<(StateId, Vec<Symbol>), (usize, usize)>,
+        wildcards: Vec<Vec<usize>>,
+    },
+}
+
+// The immutable part of a machine. States live in an arena indexed by `StateId`, and transitions
+// are grouped by (state, symbols read) in `transitions`, so that a lookup is a single index.
+#[derive(Debug, Clone)]
+pub struct Program {
+    states: Vec<State>,
+    initial_state: StateId,
+    definitions: Vec<Transition>,
+    transitions: Vec<Transition>,
+    alphabet: Alphabet,
+    index: Index,
+    tape_count: usize,
+    blank: Symbol,
+}
+impl Program {
+    pub(crate) fn new(
+        states: Vec<State>,
+        initial_state: StateId,
+        definitions: Vec<Transition>,
+        alphabet: Alphabet,
+        tape_count: usize,
+        blank: Symbol,
+    ) -> Self {
+        let mut program = Self {
+            index: Index::Dense(vec![]),
+            states,
+            initial_state,
+            definitions,
+            transitions: vec![],
+            alphabet,
+            tape_count,
+            blank,
+        };
+
+        if program.tape_count == 1 {
+            program.build_dense_index();
+        } else {
+            program.build_hashed_index();
+        }
+
+        program
+    }
+
+    // For each symbol read, only the matching transitions with the fewest wildcards are kept, so
+    // an exact match always wins over a wildcard. Among those the definition order is kept, so
+    // the first one is still the one a deterministic run takes.
+    fn build_dense_index(&mut self) {
+        let keys_per_state = self.alphabet.len();
+        let mut table = vec![(0, 0); self.states.len() * keys_per_state];
+        let mut transitions = vec![];
+        for state in 0..self.states.len() {
+            let candidates = (self.definitions)
+                .iter()
+                .filter(|t| t.from == StateId(state))
+                .collect::<Vec<_>>();
+            if candidates.is_empty() {
+                continue;
+            }
+
+            for i in 0..keys_per_state {
+                let read = [Symbol(i as u32)];
+                let matching = candidates.iter().filter(|t| t.matches(&read));
+                let Some(fewest) = matching.clone().map(|t| t.wildcard_count()).min() else {
+                    continue;
+                };
+
+                let start = transitions.len();
+                transitions.extend(
+                    matching
+                        .filter(|t| t.wildcard_count() == fewest)
+                        .map(|t| t.resolve(&read)),
+                );
+                table[state * keys_per_state + i] = (start, transitions.len());
+            }
+        }
+        self.index = Index::Dense(table);
+        self.transitions = transitions;
+    }
+
+    fn build_hashed_index(&mut self) {
+        let mut groups = HashMap::<_, Vec<&Transition>>::new();
+        let mut order = vec![];
+        let mut wildcards = vec![vec![]; self.states.len()];
+        for (i, transition) in self.definitions.iter().enumerate() {
+            if transition.wildcard_count() > 0 {
+                wildcards[transition.from.0].push(i);
+                continue;
+            }
+            let key = (transition.from, transition.read.clone());
+            if !groups.contains_key(&key) {
+                order.push(key.clone());
+            }
+            groups.entry(key).or_default().push(transition);
+        }
+
+        let mut exact = HashMap::new();
+        let mut transitions = vec![];
+        for key in order {
+            let start = transitions.len();
+            transitions.extend(groups[&key].iter().map(|&t| t.clone()));
+            exact.insert(key, (start, transitions.len()));
+        }
+        self.index = Index::Hashed { exact, wildcards };
+        self.transitions = transitions;
+    }
+
+    pub fn initial_state(&self) -> StateId {
+        self.initial_state
+    }
+
+    pub fn state(&self, id: StateId) -> &State {
+        &self.states[id.0]
+    }
+
+    pub fn state_id(&self, name: &str) -> Option<StateId> {
+        self.states.iter().position(|s| s.name == name).map(StateId)
+    }
+
+    pub fn states(&self) -> &[State] {
+        &self.states
+    }
+
+    pub fn tape_count(&self) -> usize {
+        self.tape_count
+    }
+
+    pub fn blank(&self) -> Symbol {
+        self.blank
+    }
+
+    pub fn alphabet(&self) -> &Alphabet {
+        &self.alphabet
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<Symbol> {
+        self.alphabet.get(name)
+    }
+
+    pub fn symbol_name(&self, symbol: Symbol) -> &str {
+        self.alphabet.name(symbol)
+    }
+
+    // The transitions as they were defined, wildcards included.
+    pub fn all_transitions(&self) -> &[Transition] {
+        &self.definitions
+    }
+
+    // The transitions that apply to `read` in `state`, with wildcards replaced by the symbols
+    // read. They are only borrowed unless wildcards had to be matched at lookup time.
+    pub fn transitions(&self, state: StateId, read: &[Symbol]) -> Cow<'_, [Transition]> {
+        if read.len() != self.tape_count {
+            return Cow::Borrowed(&[]);
+        }
+        let (table, wildcards) = match &self.index {
+            Index::Dense(table) => return Cow::Borrowed(self.entry(table, state, read[0])),
+            Index::Hashed { exact, wildcards } => (exact, &wildcards[state.0]),
+        };
+        if let Some(&(start, end)) = table.get(&(state, read.to_vec())) {
+            return Cow::Borrowed(&self.transitions[start..end]);
+        }
+
+        let matching = (wildcards.iter())
+            .map(|&i| &self.definitions[i])
+            .filter(|t| t.matches(read));
+        let Some(fewest) = matching.clone().map(|t| t.wildcard_count()).min() else {
+            return Cow::Borrowed(&[]);
+        };
+        Cow::Owned(
+            matching
+                .filter(|t| t.wildcard_count() == fewest)
+                .map(|t| t.resolve(read))
+                .collect(),
+        )
+    }
+
+    pub fn transition(
+        &self,
+        state: StateId,
+        read: &[Symbol],
+    ) -> Result<Cow<'_, Transition>, Stuck> {
+        match self.transitions(state, read) {
+            Cow::Borrowed(transitions) => transitions.first().map(Cow::Borrowed),
+            Cow::Owned(transitions) => transitions.into_iter().next().map(Cow::Owned),
+        }
+        .ok_or(Stuck)
+    }
+
+    pub fn is_kind(&self, state: StateId, kind: StateKind) -> bool {
+        self.state(state).kind == kind
+    }
+
+    fn entry(&self, table: &[(usize, usize)], state: StateId, symbol: Symbol) -> &[Transition] {
+        let id = symbol.0 as usize;
+        if id >= self.alphabet.len() {
+            return &[];
+        }
+        let (start, end) = table[state.0 * self.alphabet.len() + id];
+
+        &self.transitions[start..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Direction, Status, Symbol, TuringMachine, TuringMachineBuilder};
+    use anyhow::Result;
+    use std::thread;
+
+    #[test]
+    fn test_transition_table() -> Result<()> {
+        let tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C"])
+            .add_transition("B", "A", "1", "1", Direction::Left)?
+            .add_transition("A", "B", "0", "1", Direction::Right)?
+            .add_transition("A", "C", "0", "0", Direction::Stay)?
+            .add_transition("A", "A", "_", "_", Direction::Right)?
+            .set_initial_state("A")?
+            .set_tape(vec!["0".to_string()])
+            .set_blank_symbol("_")
+            .build()?;
+
+        let program = &tm.program;
+        let a = program.state_id("A").unwrap();
+        let [zero, one] = ["0", "1"].map(|name| program.symbol(name).unwrap());
+        let targets = program
+            .transitions(a, &[zero])
+            .iter()
+            .map(|t| program.state(t.to).name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(targets, vec!["B", "C"]);
+        assert_eq!(program.transitions(a, &[one]).len(), 0);
+        assert_eq!(program.symbol("x"), None);
+        assert!(program
+            .transition(program.state_id("C").unwrap(), &[zero])
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wildcard() -> Result<()> {
+        let tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C", "D"])
+            .add_transition("A", "B", "*", "*", Direction::Right)?
+            .add_transition("A", "C", "1", "0", Direction::Left)?
+            .add_transition("A", "D", "1", "*", Direction::Stay)?
+            .set_initial_state("A")?
+            .set_tape(vec!["1".to_string()])
+            .set_blank_symbol("_")
+            .build()?;
+
+        let program = &tm.program;
+        let a = program.state_id("A").unwrap();
+        let [blank, one] = ["_", "1"].map(|name| program.symbol(name).unwrap());
+        let transitions = program.transitions(a, &[one]);
+        assert_eq!(transitions.len(), 2);
+        assert_eq!(program.state(transitions[0].to).name, "C");
+        assert_eq!(transitions[1].write, vec![one]);
+
+        let transition = program.transition(a, &[blank]).unwrap();
+        assert_eq!(program.state(transition.to).name, "B");
+        assert_eq!(transition.read, vec![blank]);
+        assert_eq!(transition.write, vec![blank]);
+        assert_eq!(program.all_transitions()[0].read, vec![Symbol::ANY]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_tape_wildcard() -> Result<()> {
+        // 26 symbols on 6 tapes would take over 300 million entries per state in a dense table.
+        let letters = ('a'..='z').map(String::from).collect::<Vec<_>>();
+        let mut builder = TuringMachineBuilder::new()
+            .add_states(&["A", "B", "C", "D"])
+            .add_multi_tape_transition("A", "B", &["*"; 6], &["*"; 6], &[Direction::Stay; 6])?
+            .add_multi_tape_transition(
+                "A",
+                "C",
+                &["a", "*", "*", "*", "*", "*"],
+                &["x", "*", "*", "*", "*", "*"],
+                &[Direction::Right; 6],
+            )?
+            .add_multi_tape_transition("A", "D", &["a"; 6], &["a"; 6], &[Direction::Left; 6])?
+            .set_initial_state("A")?
+            .set_tape_count(6)
+            .set_blank_symbol("_");
+        for letter in &letters {
+            builder = builder.add_multi_tape_transition(
+                "D",
+                "D",
+                &[letter.as_str(); 6],
+                &[letter.as_str(); 6],
+                &[Direction::Stay; 6],
+            )?;
+        }
+        let tm = builder.build()?;
+
+        let program = &tm.program;
+        let a = program.state_id("A").unwrap();
+        let [a_, b_] = ["a", "b"].map(|name| program.symbol(name).unwrap());
+        let targets = |read: &[Symbol]| {
+            (program.transitions(a, read).iter())
+                .map(|t| program.state(t.to).name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(targets(&[a_; 6]), vec!["D"]);
+        assert_eq!(targets(&[a_, b_, b_, b_, b_, b_]), vec!["C"]);
+        assert_eq!(targets(&[b_; 6]), vec!["B"]);
+        assert_eq!(targets(&[b_; 5]), Vec::<String>::new());
+
+        let transition = program.transition(a, &[a_, b_, a_, b_, a_, b_])?;
+        assert_eq!(
+            program.alphabet().names(&transition.write),
+            vec!["x", "b", "a", "b", "a", "b"]
+        );
+        assert_eq!(transition.read, vec![a_, b_, a_, b_, a_, b_]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_sync() -> Result<()> {
+        let tm = TuringMachineBuilder::new()
+            .add_states(&["A", "B"])
+            .add_transition("A", "A", "1", "0", Direction::Right)?
+            .add_transition("A", "B", "_", "_", Direction::Left)?
+            .set_initial_state("A")?
+            .set_accept_states(&["B"])?
+            .set_tape(vec!["1".to_string(), "1".to_string()])
+            .set_blank_symbol("_")
+            .build()?;
+
+        let handles = (0..4)
+            .map(|_| {
+                let mut tm: TuringMachine = tm.clone();
+                thread::spawn(move || tm.run())
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Status::Accept);
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
+use crate::symbol::Symbol;
 use serde::Deserialize;
-use std::{cell::RefCell, rc::Rc};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -27,56 +27,141 @@ pub enum Direction {
         alias = "→"
     )]
     Right,
-}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Transition {
-    to: Rc<RefCell<State>>,
-    read: String,
-    write: String,
-    move_head: Direction,
+    #[serde(
+        alias = "stay",
+        alias = "S",
+        alias = "s",
+        alias = "N",
+        alias = "n",
+        alias = "-",
+        alias = "·"
+    )]
+    Stay,
+
+    #[serde(alias = "up", alias = "U", alias = "u", alias = "↑", alias = "^")]
+    Up,
+
+    #[serde(alias = "down", alias = "D", alias = "d", alias = "↓", alias = "v")]
+    Down,
+
+    #[serde(alias = "forward", alias = "F", alias = "f")]
+    Forward,
+
+    #[serde(alias = "turn_left", alias = "TL", alias = "tl", alias = "↺")]
+    TurnLeft,
+
+    #[serde(alias = "turn_right", alias = "TR", alias = "tr", alias = "↻")]
+    TurnRight,
+
+    #[serde(alias = "u_turn", alias = "UT", alias = "ut")]
+    UTurn,
 }
-impl Transition {
-    pub fn new(to: &Rc<RefCell<State>>, read: &str, write: &str, move_head: Direction) -> Self {
-        Transition {
-            to: to.clone(),
-            read: read.to_string(),
-            write: write.to_string(),
-            move_head,
-        }
+impl Direction {
+    pub fn is_linear(&self) -> bool {
+        matches!(self, Direction::Left | Direction::Right | Direction::Stay)
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StateId(pub usize);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum StateKind {
+    #[default]
+    Normal,
+    Accept,
+    Reject,
+    Halt,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct State {
     pub name: String,
-    transitions: Vec<Transition>,
+    pub kind: StateKind,
 }
 impl State {
-    pub fn new(name: &str, transitions: impl AsRef<[Transition]>) -> Self {
+    pub fn new(name: &str) -> Self {
         Self {
             name: name.to_string(),
-            transitions: transitions.as_ref().to_vec(),
+            kind: StateKind::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub from: StateId,
+    pub to: StateId,
+    pub read: Vec<Symbol>,
+    pub write: Vec<Symbol>,
+    pub move_head: Vec<Direction>,
+    // How likely a probabilistic run is to take this transition, relative to the other
+    // transitions for the same state and symbols.
+    pub weight: u32,
+}
+impl Transition {
+    pub fn new(
+        from: StateId,
+        to: StateId,
+        read: Symbol,
+        write: Symbol,
+        move_head: Direction,
+    ) -> Self {
+        Self::new_multi_tape(from, to, &[read], &[write], &[move_head])
+    }
+
+    pub fn new_multi_tape(
+        from: StateId,
+        to: StateId,
+        read: &[Symbol],
+        write: &[Symbol],
+        move_head: &[Direction],
+    ) -> Self {
+        Transition {
+            from,
+            to,
+            read: read.to_vec(),
+            write: write.to_vec(),
+            move_head: move_head.to_vec(),
+            weight: 1,
         }
     }
 
-    pub fn add_transition(&mut self, transition: Transition) {
-        self.transitions.push(transition);
+    pub fn with_weight(self, weight: u32) -> Self {
+        Self { weight, ..self }
+    }
+
+    pub fn is_linear(&self) -> bool {
+        self.move_head.iter().all(Direction::is_linear)
+    }
+
+    pub fn tape_count(&self) -> Option<usize> {
+        let count = self.read.len();
+        (self.write.len() == count && self.move_head.len() == count).then_some(count)
     }
 
-    pub fn add_transitions(&mut self, transitions: impl AsRef<[Transition]>) {
-        self.transitions.extend_from_slice(transitions.as_ref());
+    pub fn wildcard_count(&self) -> usize {
+        self.read.iter().filter(|&&s| s == Symbol::ANY).count()
     }
 
-    pub fn transition(&self, read: &str) -> Result<(Rc<RefCell<State>>, String, Direction), Stuck> {
-        let transition = self.transitions.iter().find(|&t| t.read == read);
-        match transition {
-            Some(transition) => Ok((
-                transition.to.clone(),
-                transition.write.clone(),
-                transition.move_head.clone(),
-            )),
-            None => Err(Stuck),
+    pub fn matches(&self, read: &[Symbol]) -> bool {
+        (self.read.len() == read.len())
+            && (self.read.iter().zip(read)).all(|(&s, &r)| s == Symbol::ANY || s == r)
+    }
+
+    // The transition taken for `read`, with wildcards replaced by the symbols actually read.
+    pub fn resolve(&self, read: &[Symbol]) -> Self {
+        let resolve = |symbols: &[Symbol]| {
+            (symbols.iter().zip(read))
+                .map(|(&s, &r)| if s == Symbol::ANY { r } else { s })
+                .collect()
+        };
+
+        Self {
+            read: resolve(&self.read),
+            write: resolve(&self.write),
+            ..self.clone()
         }
     }
 }
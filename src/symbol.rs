@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(pub u32);
+impl Symbol {
+    // Read by a transition, matches any symbol. Written, writes back the symbol that was read.
+    pub const ANY: Symbol = Symbol(u32::MAX);
+    pub const ANY_NAME: &'static str = "*";
+}
+
+// Maps tape symbols to compact IDs. Symbols are interned once while a machine is built, and only
+// turned back into strings for display.
+#[derive(Debug, Clone, Default)]
+pub struct Alphabet {
+    names: Vec<String>,
+    ids: HashMap<String, Symbol>,
+}
+impl Alphabet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.ids.get(name) {
+            return symbol;
+        }
+
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, symbol: Symbol) -> &str {
+        if symbol == Symbol::ANY {
+            return Symbol::ANY_NAME;
+        }
+
+        &self.names[symbol.0 as usize]
+    }
+
+    pub fn names(&self, symbols: &[Symbol]) -> Vec<&str> {
+        symbols.iter().map(|&s| self.name(s)).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Symbol, &str)> {
+        (self.names.iter())
+            .enumerate()
+            .map(|(i, name)| (Symbol(i as u32), name.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut alphabet = Alphabet::new();
+        let a = alphabet.intern("a");
+        let b = alphabet.intern("b'");
+
+        assert_eq!(alphabet.intern("a"), a);
+        assert_ne!(a, b);
+        assert_eq!(alphabet.len(), 2);
+        assert_eq!(alphabet.get("b'"), Some(b));
+        assert_eq!(alphabet.get("c"), None);
+        assert_eq!(alphabet.names(&[b, a, b]), vec!["b'", "a", "b'"]);
+    }
+}
@@ -0,0 +1,407 @@
+use crate::{state::Direction, symbol::Symbol};
+use serde::{de::IntoDeserializer, Deserialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    ops::Range,
+    str::FromStr,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("Tape head moved out of bounds")]
+pub struct OutOfBounds;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum TapeModel {
+    #[default]
+    #[serde(alias = "two_way", alias = "two-way")]
+    TwoWay,
+
+    #[serde(alias = "one_way", alias = "one-way", alias = "semi_infinite")]
+    OneWay,
+
+    #[serde(alias = "one_way_strict", alias = "one-way-strict")]
+    OneWayStrict,
+
+    #[serde(
+        alias = "linear_bounded",
+        alias = "linear-bounded",
+        alias = "lba",
+        alias = "LBA"
+    )]
+    LinearBounded,
+}
+impl FromStr for TapeModel {
+    type Err = serde::de::value::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(s.into_deserializer())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum TapeBackend {
+    #[default]
+    #[serde(alias = "deque")]
+    Deque,
+
+    #[serde(alias = "sparse")]
+    Sparse,
+}
+impl TapeBackend {
+    pub fn new_tape(self, symbols: &[Symbol], blank: Symbol) -> Box<dyn Tape> {
+        match self {
+            TapeBackend::Deque => Box::new(DequeTape::new(symbols, blank)),
+            TapeBackend::Sparse => Box::new(SparseTape::new(symbols, blank)),
+        }
+    }
+}
+impl FromStr for TapeBackend {
+    type Err = serde::de::value::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(s.into_deserializer())
+    }
+}
+
+// How the result of a machine that computes a function is read off a tape. `Trim` takes every
+// cell but the blanks at both ends, and `FromHead` the cells from the head up to the first blank.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum OutputMode {
+    #[default]
+    #[serde(alias = "trim")]
+    Trim,
+
+    #[serde(alias = "from_head", alias = "from-head", alias = "head")]
+    FromHead,
+}
+impl FromStr for OutputMode {
+    type Err = serde::de::value::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(s.into_deserializer())
+    }
+}
+
+// Positions are relative to the first input cell. A tape keeps track of the cells the head has
+// visited, and everything outside of `bounds` reads as blank.
+pub trait Tape: Debug + Send + Sync {
+    fn blank(&self) -> Symbol;
+
+    fn head(&self) -> isize;
+
+    fn bounds(&self) -> Range<isize>;
+
+    fn get(&self, position: isize) -> Symbol;
+
+    // Writes a cell, extending `bounds` to cover it.
+    fn set(&mut self, position: isize, symbol: Symbol);
+
+    // Moves the head, extending `bounds` to cover the new position.
+    fn set_head(&mut self, position: isize);
+
+    fn clone_box(&self) -> Box<dyn Tape>;
+
+    fn read(&self) -> Symbol {
+        self.get(self.head())
+    }
+
+    fn write(&mut self, symbol: Symbol) {
+        self.set(self.head(), symbol);
+    }
+
+    fn symbols(&self) -> Vec<Symbol> {
+        self.bounds().map(|position| self.get(position)).collect()
+    }
+
+    // Reads the output within `cells`, which leaves out the end markers of a linear bounded tape.
+    fn output(&self, cells: Range<isize>, mode: OutputMode) -> Vec<Symbol> {
+        let blank = self.blank();
+        match mode {
+            OutputMode::Trim => {
+                let start = cells.clone().find(|&p| self.get(p) != blank);
+                let end = cells.clone().rev().find(|&p| self.get(p) != blank);
+                match start.zip(end) {
+                    Some((start, end)) => (start..=end).map(|p| self.get(p)).collect(),
+                    None => vec![],
+                }
+            }
+            OutputMode::FromHead => (self.head().max(cells.start)..cells.end)
+                .map(|p| self.get(p))
+                .take_while(|&symbol| symbol != blank)
+                .collect(),
+        }
+    }
+
+    fn add_end_markers(&mut self, left_marker: Symbol, right_marker: Symbol) {
+        let bounds = self.bounds();
+        self.set(bounds.start - 1, left_marker);
+        self.set(bounds.end, right_marker);
+    }
+
+    fn check_move(&self, direction: Direction, model: TapeModel) -> Result<(), OutOfBounds> {
+        let bounds = self.bounds();
+        let at_left_end = self.head() == bounds.start;
+        let at_right_end = self.head() + 1 == bounds.end;
+        match (model, direction) {
+            (TapeModel::OneWayStrict | TapeModel::LinearBounded, Direction::Left)
+                if at_left_end =>
+            {
+                Err(OutOfBounds)
+            }
+            (TapeModel::LinearBounded, Direction::Right) if at_right_end => Err(OutOfBounds),
+            _ => Ok(()),
+        }
+    }
+
+    // Moves that `check_move` rejects leave the head where it is, and so does moving left off a
+    // one-way tape.
+    fn move_head(&mut self, direction: Direction, model: TapeModel) {
+        if self.check_move(direction, model).is_err() {
+            return;
+        }
+
+        let head = self.head();
+        match direction {
+            Direction::Left => {
+                if head > self.bounds().start || model == TapeModel::TwoWay {
+                    self.set_head(head - 1);
+                }
+            }
+            Direction::Right => self.set_head(head + 1),
+            Direction::Stay => {}
+            _ => unreachable!("{:?} can only be used on a grid tape", direction),
+        }
+    }
+}
+// The part of a tape that makes up a configuration: the symbols between the first and the last
+// non-blank cell, where the first of them is, and where the head is.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct TapeContent {
+    symbols: Vec<Symbol>,
+    first: isize,
+    head: isize,
+}
+
+impl dyn Tape + '_ {
+    pub(crate) fn hash_content(&self, state: &mut impl Hasher) {
+        self.content().hash(state);
+    }
+
+    pub(crate) fn content(&self) -> TapeContent {
+        // Blanks at both ends of the tape are not part of the configuration, so neither the
+        // backend nor how far the tape has grown affects it.
+        let blank = self.blank();
+        let bounds = self.bounds();
+        let first = bounds
+            .clone()
+            .find(|&position| self.get(position) != blank)
+            .unwrap_or(0);
+        let last = bounds
+            .clone()
+            .rev()
+            .find(|&position| self.get(position) != blank)
+            .map_or(first, |position| position + 1);
+
+        TapeContent {
+            symbols: (first..last).map(|position| self.get(position)).collect(),
+            first,
+            head: self.head(),
+        }
+    }
+}
+impl Clone for Box<dyn Tape> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+// Stores every cell between the leftmost and rightmost visited positions. Growing to either side
+// is amortised O(1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DequeTape {
+    cells: VecDeque<Symbol>,
+    head: isize,
+    origin: isize,
+    blank: Symbol,
+}
+impl DequeTape {
+    pub fn new(symbols: &[Symbol], blank: Symbol) -> Self {
+        let mut cells = symbols.iter().copied().collect::<VecDeque<_>>();
+        if cells.is_empty() {
+            cells.push_back(blank);
+        }
+
+        Self {
+            cells,
+            head: 0,
+            origin: 0,
+            blank,
+        }
+    }
+
+    fn extend_to(&mut self, position: isize) {
+        while position < -self.origin {
+            self.cells.push_front(self.blank);
+            self.origin += 1;
+        }
+        while position >= self.cells.len() as isize - self.origin {
+            self.cells.push_back(self.blank);
+        }
+    }
+}
+impl Tape for DequeTape {
+    fn blank(&self) -> Symbol {
+        self.blank
+    }
+
+    fn head(&self) -> isize {
+        self.head
+    }
+
+    fn bounds(&self) -> Range<isize> {
+        -self.origin..self.cells.len() as isize - self.origin
+    }
+
+    fn get(&self, position: isize) -> Symbol {
+        usize::try_from(position + self.origin)
+            .ok()
+            .and_then(|i| self.cells.get(i))
+            .copied()
+            .unwrap_or(self.blank)
+    }
+
+    fn set(&mut self, position: isize, symbol: Symbol) {
+        self.extend_to(position);
+        self.cells[(position + self.origin) as usize] = symbol;
+    }
+
+    fn set_head(&mut self, position: isize) {
+        self.extend_to(position);
+        self.head = position;
+    }
+
+    fn clone_box(&self) -> Box<dyn Tape> {
+        Box::new(self.clone())
+    }
+}
+
+// Stores only non-blank cells, so a head that wanders far over blanks costs no memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseTape {
+    cells: HashMap<isize, Symbol>,
+    head: isize,
+    bounds: Range<isize>,
+    blank: Symbol,
+}
+impl SparseTape {
+    pub fn new(symbols: &[Symbol], blank: Symbol) -> Self {
+        let cells = (0..)
+            .zip(symbols.iter().copied())
+            .filter(|&(_, symbol)| symbol != blank)
+            .collect();
+
+        Self {
+            cells,
+            head: 0,
+            bounds: 0..symbols.len().max(1) as isize,
+            blank,
+        }
+    }
+
+    fn extend_to(&mut self, position: isize) {
+        self.bounds.start = self.bounds.start.min(position);
+        self.bounds.end = self.bounds.end.max(position + 1);
+    }
+}
+impl Tape for SparseTape {
+    fn blank(&self) -> Symbol {
+        self.blank
+    }
+
+    fn head(&self) -> isize {
+        self.head
+    }
+
+    fn bounds(&self) -> Range<isize> {
+        self.bounds.clone()
+    }
+
+    fn get(&self, position: isize) -> Symbol {
+        self.cells.get(&position).copied().unwrap_or(self.blank)
+    }
+
+    fn set(&mut self, position: isize, symbol: Symbol) {
+        self.extend_to(position);
+        if symbol == self.blank {
+            self.cells.remove(&position);
+        } else {
+            self.cells.insert(position, symbol);
+        }
+    }
+
+    fn set_head(&mut self, position: isize) {
+        self.extend_to(position);
+        self.head = position;
+    }
+
+    fn clone_box(&self) -> Box<dyn Tape> {
+        Box::new(self.clone())
+    }
+}
+
+pub(crate) fn read_tapes(tapes: &[Box<dyn Tape>]) -> Vec<Symbol> {
+    tapes.iter().map(|tape| tape.read()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backends() {
+        let [blank, a, b] = [0, 1, 2].map(Symbol);
+        for backend in [TapeBackend::Deque, TapeBackend::Sparse] {
+            let mut tape = backend.new_tape(&[a, b], blank);
+            tape.move_head(Direction::Left, TapeModel::TwoWay);
+            tape.move_head(Direction::Left, TapeModel::TwoWay);
+            tape.write(b);
+            assert_eq!(tape.head(), -2);
+            assert_eq!(tape.bounds(), -2..2);
+            assert_eq!(tape.symbols(), vec![b, blank, a, b]);
+            assert_eq!(tape.get(-100), blank);
+
+            let mut tape = backend.new_tape(&[a], blank);
+            tape.move_head(Direction::Left, TapeModel::OneWay);
+            assert_eq!(tape.head(), 0);
+            assert!(tape
+                .check_move(Direction::Left, TapeModel::OneWayStrict)
+                .is_err());
+
+            tape.add_end_markers(b, b);
+            assert_eq!(tape.symbols(), vec![b, a, b]);
+            assert_eq!(tape.bounds(), -1..2);
+            assert!(tape
+                .check_move(Direction::Left, TapeModel::LinearBounded)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn test_hash_content() {
+        let [blank, a] = [0, 1].map(Symbol);
+        let hash = |tape: &dyn Tape| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            tape.hash_content(&mut hasher);
+            hasher.finish()
+        };
+
+        let mut deque = TapeBackend::Deque.new_tape(&[a], blank);
+        let sparse = TapeBackend::Sparse.new_tape(&[a], blank);
+        deque.move_head(Direction::Left, TapeModel::TwoWay);
+        deque.move_head(Direction::Right, TapeModel::TwoWay);
+        assert_eq!(hash(deque.as_ref()), hash(sparse.as_ref()));
+    }
+}
@@ -1,16 +1,35 @@
-use crate::state::{Direction, State};
+use crate::{
+    history::{History, NotInHistory, Step},
+    observer::{StepEvent, StepObserver},
+    oracle::OracleLink,
+    program::Program,
+    state::{Direction, StateId, StateKind, Transition},
+    symbol::Symbol,
+    tape::{read_tapes, OutputMode, Tape, TapeBackend, TapeContent, TapeModel},
+};
 use core::fmt;
+use serde::{Deserialize, Serialize};
 use std::{
-    cell::RefCell,
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, HashMap},
     fmt::{Display, Formatter},
-    rc::Rc,
+    hash::{Hash, Hasher},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Status {
     Running,
     Accept,
     Reject,
+    Halt,
+    Stuck,
+    OutOfBounds,
+    Timeout,
+    // A nondeterministic run kept more branches than it was allowed to.
+    FrontierExceeded,
+    Loop { start: usize, period: usize },
 }
 impl Display for Status {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -18,102 +37,385 @@ impl Display for Status {
             Status::Running => write!(f, "Running"),
             Status::Accept => write!(f, "Accept"),
             Status::Reject => write!(f, "Reject"),
+            Status::Halt => write!(f, "Halt"),
+            Status::Stuck => write!(f, "Stuck"),
+            Status::OutOfBounds => write!(f, "OutOfBounds"),
+            Status::Timeout => write!(f, "Timeout"),
+            Status::FrontierExceeded => write!(f, "FrontierExceeded"),
+            Status::Loop { .. } => write!(f, "Loop"),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Snapshot {
-    pub current_state: String,
-    pub next_state: String,
-    pub tape: Vec<String>,
-    pub tape_ptr: usize,
-    pub start_ptr: usize,
-    pub read: String,
-    pub write: String,
+    pub current_state: StateId,
+    pub next_state: StateId,
+    pub tapes: Vec<Box<dyn Tape>>,
+    pub read: Vec<Symbol>,
+    pub write: Vec<Symbol>,
     pub status: Status,
 }
+impl Snapshot {
+    pub fn configuration_hash(&self) -> u64 {
+        configuration_hash(self.current_state, &self.tapes)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct TuringMachine {
-    pub current_state: Rc<RefCell<State>>,
-    accept_states: Vec<Rc<RefCell<State>>>,
-    pub tape: Vec<String>,
-    pub tape_ptr: usize,
-    pub start_ptr: usize,
-    pub blank: String,
+    pub program: Arc<Program>,
+    pub current_state: StateId,
+    pub tapes: Vec<Box<dyn Tape>>,
+    pub tape_model: TapeModel,
+    pub tape_backend: TapeBackend,
+    // The left and right markers put around the input by `add_end_markers`, if any.
+    pub end_markers: Option<(Symbol, Symbol)>,
     pub status: Status,
-    pub snapshots: Vec<Snapshot>,
+    pub history: History,
+    pub record_history: bool,
+    pub steps: usize,
+    pub max_steps: Option<usize>,
+    pub timeout: Option<Duration>,
+    // Set for a probabilistic machine, which chooses among the transitions that apply by their
+    // weights instead of taking the first one.
+    pub seed: Option<u64>,
+    pub oracle: Option<OracleLink>,
+    pub(crate) started_at: Option<Instant>,
+    pub(crate) seen_configurations: Option<HashMap<Configuration, usize>>,
+    pub(crate) end_status: Option<Status>,
 }
 impl TuringMachine {
-    pub fn new(
-        initial_state: &Rc<RefCell<State>>,
-        accept_states: &[Rc<RefCell<State>>],
-        tape: &[impl ToString],
-        blank: impl ToString,
-    ) -> Self {
+    // The input goes on the first tape and every other tape starts out blank.
+    pub fn new(program: Arc<Program>, tape: &[Symbol]) -> Self {
+        Self::with_tape_backend(program, tape, TapeBackend::default())
+    }
+
+    pub fn with_tape_backend(program: Arc<Program>, tape: &[Symbol], backend: TapeBackend) -> Self {
+        let blank = program.blank();
+        let mut tapes = vec![backend.new_tape(tape, blank)];
+        tapes.resize(program.tape_count().max(1), backend.new_tape(&[], blank));
+
         Self {
-            current_state: initial_state.clone(),
-            accept_states: accept_states.to_vec(),
-            tape: tape.into_iter().map(|s| s.to_string()).collect(),
-            tape_ptr: 0,
-            start_ptr: 0,
+            current_state: program.initial_state(),
+            program,
+            history: History::new(&tapes),
+            record_history: true,
+            tapes,
+            tape_model: TapeModel::default(),
+            tape_backend: backend,
+            end_markers: None,
             status: Status::Running,
-            blank: blank.to_string(),
-            snapshots: Vec::new(),
+            steps: 0,
+            max_steps: None,
+            timeout: None,
+            seed: None,
+            oracle: None,
+            started_at: None,
+            seen_configurations: None,
+            end_status: None,
+        }
+    }
+
+    pub fn tape_count(&self) -> usize {
+        self.tapes.len()
+    }
+
+    pub fn blank(&self) -> Symbol {
+        self.program.blank()
+    }
+
+    pub fn current_state_name(&self) -> &str {
+        &self.program.state(self.current_state).name
+    }
+
+    pub fn tape_contents(&self, index: usize) -> Vec<&str> {
+        self.program.alphabet().names(&self.tapes[index].symbols())
+    }
+
+    // The result left on tape `index`, with the names of its symbols joined together.
+    pub fn output(&self, index: usize, mode: OutputMode) -> String {
+        self.output_symbols(index, mode).concat()
+    }
+
+    // The end markers of a linear bounded tape are never part of the result. A linear bounded tape
+    // without markers is read as a whole.
+    pub fn output_symbols(&self, index: usize, mode: OutputMode) -> Vec<&str> {
+        self.tape_output(self.tapes[index].as_ref(), mode)
+    }
+
+    pub(crate) fn tape_output(&self, tape: &dyn Tape, mode: OutputMode) -> Vec<&str> {
+        let mut cells = tape.bounds();
+        if self.tape_model == TapeModel::LinearBounded && self.end_markers.is_some() {
+            cells = cells.start + 1..cells.end - 1;
+        }
+
+        self.program.alphabet().names(&tape.output(cells, mode))
+    }
+
+    pub fn set_tape_model(&mut self, tape_model: TapeModel) {
+        self.tape_model = tape_model;
+    }
+
+    pub fn add_end_markers(&mut self, left_marker: Symbol, right_marker: Symbol) {
+        for tape in &mut self.tapes {
+            tape.add_end_markers(left_marker, right_marker);
+        }
+        self.end_markers = Some((left_marker, right_marker));
+        self.history = History::new(&self.tapes);
+    }
+
+    // Moves the head of the first tape before the machine starts.
+    pub fn set_head(&mut self, position: isize) {
+        self.tapes[0].set_head(position);
+        self.history = History::new(&self.tapes);
+    }
+
+    pub fn enable_loop_detection(&mut self) {
+        self.seen_configurations.get_or_insert_with(HashMap::new);
+    }
+
+    pub fn configuration_hash(&self) -> u64 {
+        configuration_hash(self.current_state, &self.tapes)
+    }
+
+    pub(crate) fn configuration(&self) -> Configuration {
+        Configuration::new(self.current_state, &self.tapes)
+    }
+
+    pub fn run(&mut self) -> Status {
+        for _ in self.by_ref() {}
+
+        self.status
+    }
+
+    // Runs the machine like `run`, passing every step to `observer` as well as to the history.
+    pub fn run_with_observer(&mut self, observer: &mut dyn StepObserver) -> Status {
+        while self.next_with_observer(observer).is_some() {}
+
+        self.status
+    }
+
+    pub fn run_with_limit(
+        &mut self,
+        max_steps: Option<usize>,
+        timeout: Option<Duration>,
+    ) -> Status {
+        self.max_steps = max_steps;
+        self.timeout = timeout;
+        self.started_at = None;
+
+        self.run()
+    }
+
+    // Restores the configuration the machine was in after `step` steps. Seeking back to the end of
+    // the history also restores the status the machine stopped with.
+    pub fn seek(&mut self, step: usize) -> Result<(), NotInHistory> {
+        // Steps taken while `record_history` was off leave a gap up to the current step.
+        if self.steps > self.history.end() {
+            return (step == self.steps).then_some(()).ok_or(NotInHistory(step));
         }
+        let tapes = self.history.tapes(step).ok_or(NotInHistory(step))?;
+        if self.status != Status::Running && self.steps >= self.history.end() {
+            self.end_status = Some(self.status);
+        }
+
+        let next = self.history.step(step);
+        let last = self.history.steps().last();
+        self.current_state = match (next, last) {
+            (Some(next), _) => next.current_state,
+            (None, Some(last)) => last.next_state,
+            (None, None) => self.current_state,
+        };
+        self.tapes = tapes;
+        self.status = match next {
+            Some(next) => next.status,
+            None => self.end_status.unwrap_or(Status::Running),
+        };
+        self.steps = step;
+        if let Some(seen_configurations) = self.seen_configurations.as_mut() {
+            seen_configurations.retain(|_, &mut seen_at| seen_at < step);
+        }
+
+        Ok(())
+    }
+
+    pub fn step_back(&mut self) -> Option<StateId> {
+        let step = self.steps.min(self.history.end()).checked_sub(1)?;
+        self.seek(step).ok()?;
+
+        Some(self.current_state)
+    }
+
+    fn is_current_state(&self, kind: StateKind) -> bool {
+        self.program.is_kind(self.current_state, kind)
+    }
+
+    pub(crate) fn is_limit_exceeded(&self) -> bool {
+        is_limit_exceeded(self.steps, self.max_steps, self.timeout, self.started_at)
     }
 }
-impl Iterator for TuringMachine {
-    type Item = Rc<RefCell<State>>;
+impl TuringMachine {
+    // Takes a single step like `next`, and tells `observer` about it, or about the machine
+    // stopping.
+    pub fn next_with_observer(&mut self, observer: &mut dyn StepObserver) -> Option<StateId> {
+        let was_running = self.status == Status::Running;
+        let next = self.take_step(observer);
+        if was_running && self.status != Status::Running {
+            observer.on_halt(self);
+        }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let read = self.tape[self.tape_ptr].clone();
-        let (next_state, write, move_head) = self
-            .current_state
-            .borrow()
-            .transition(&read)
-            .map_err(|_| {
-                self.status = match self
-                    .accept_states
-                    .iter()
-                    .find(|&s| s.borrow().name == self.current_state.borrow().name)
-                {
-                    Some(_) => Status::Accept,
-                    None => Status::Reject,
-                };
-            })
-            .ok()?;
-        self.snapshots.push(Snapshot {
-            current_state: self.current_state.borrow().name.clone(),
-            next_state: next_state.borrow().name.clone(),
-            tape: self.tape.clone(),
-            tape_ptr: self.tape_ptr,
-            start_ptr: self.start_ptr,
-            read: read.clone(),
-            write: write.clone(),
-            status: self.status.clone(),
-        });
+        next
+    }
 
-        self.current_state = next_state;
-        self.tape[self.tape_ptr] = write;
-        match move_head {
-            Direction::Left => {
-                if self.tape_ptr == 0 {
-                    self.tape.insert(0, self.blank.clone());
-                    self.start_ptr += 1;
-                } else {
-                    self.tape_ptr -= 1;
-                }
+    fn take_step(&mut self, observer: &mut dyn StepObserver) -> Option<StateId> {
+        if self.status != Status::Running {
+            return None;
+        }
+        if self.is_current_state(StateKind::Reject) {
+            self.status = Status::Reject;
+            return None;
+        }
+        if self.is_current_state(StateKind::Halt) {
+            self.status = Status::Halt;
+            return None;
+        }
+
+        let read = read_tapes(&self.tapes);
+        // Asking the oracle takes a step that leaves the tapes as they are.
+        let answer = self.oracle_answer().map(|state| {
+            let stay = vec![Direction::Stay; read.len()];
+            Transition::new_multi_tape(self.current_state, state, &read, &read, &stay)
+        });
+        let transition = match (answer, self.seed) {
+            (Some(answer), _) => Ok(Cow::Owned(answer)),
+            (None, Some(seed)) => {
+                (self.program).random_transition(self.current_state, &read, seed, self.steps)
             }
-            Direction::Right => {
-                self.tape_ptr += 1;
-                if self.tape_ptr == self.tape.len() {
-                    self.tape.push(self.blank.clone());
-                }
+            (None, None) => self.program.transition(self.current_state, &read),
+        };
+        let Ok(transition) = transition else {
+            self.status = if self.is_current_state(StateKind::Accept) {
+                Status::Accept
+            } else {
+                Status::Stuck
+            };
+            return None;
+        };
+        let is_out_of_bounds = self
+            .tapes
+            .iter()
+            .zip(&transition.move_head)
+            .any(|(tape, &direction)| tape.check_move(direction, self.tape_model).is_err());
+        if is_out_of_bounds {
+            self.status = Status::OutOfBounds;
+            return None;
+        }
+        // The limits only stop a machine that would otherwise take another step, so a run that
+        // stops in exactly `max_steps` steps still gets its own status.
+        self.started_at.get_or_insert_with(Instant::now);
+        if self.is_limit_exceeded() {
+            self.status = Status::Timeout;
+            return None;
+        }
+        // The whole configuration is kept rather than only its hash, so a hash collision cannot
+        // be taken for a loop.
+        let configuration = self
+            .seen_configurations
+            .is_some()
+            .then(|| self.configuration());
+        if let (Some(configuration), Some(seen_configurations)) =
+            (configuration, self.seen_configurations.as_mut())
+        {
+            if let Some(&start) = seen_configurations.get(&configuration) {
+                self.status = Status::Loop {
+                    start,
+                    period: self.steps - start,
+                };
+                return None;
             }
+            seen_configurations.insert(configuration, self.steps);
+        }
+        if self.record_history && self.history.end() < self.steps {
+            self.history = History::starting_at(&self.tapes, self.steps);
         }
+        let mut head_moves = vec![];
+        let moves = transition.write.iter().zip(&transition.move_head);
+        for (tape, (write, &direction)) in self.tapes.iter_mut().zip(moves) {
+            let head = tape.head();
+            tape.write(*write);
+            tape.move_head(direction, self.tape_model);
+            head_moves.push((head, tape.head()));
+        }
+        let step = Step {
+            current_state: self.current_state,
+            next_state: transition.to,
+            read,
+            write: transition.write.clone(),
+            head_moves,
+            status: self.status,
+        };
+        let event = StepEvent {
+            number: self.steps,
+            step: &step,
+            tapes: &self.tapes,
+            program: &self.program,
+        };
+        if self.record_history {
+            self.history.on_step(&event);
+        }
+        observer.on_step(&event);
+        self.end_status = None;
+        self.current_state = transition.to;
+        self.steps += 1;
+
+        Some(self.current_state)
+    }
+}
+impl Iterator for TuringMachine {
+    type Item = StateId;
 
-        Some(self.current_state.clone())
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_observer(&mut |_: &StepEvent| {})
     }
 }
+
+pub(crate) fn is_limit_exceeded(
+    steps: usize,
+    max_steps: Option<usize>,
+    timeout: Option<Duration>,
+    started_at: Option<Instant>,
+) -> bool {
+    let steps_exceeded = max_steps.is_some_and(|max| steps >= max);
+    let time_exceeded = match (timeout, started_at) {
+        (Some(timeout), Some(started_at)) => started_at.elapsed() >= timeout,
+        _ => false,
+    };
+
+    steps_exceeded || time_exceeded
+}
+
+// The state and the content of every tape, i.e. what `configuration_hash` hashes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Configuration {
+    state: StateId,
+    tapes: Vec<TapeContent>,
+}
+impl Configuration {
+    pub(crate) fn new(state: StateId, tapes: &[Box<dyn Tape>]) -> Self {
+        Self {
+            state,
+            tapes: tapes.iter().map(|tape| tape.content()).collect(),
+        }
+    }
+}
+
+pub(crate) fn configuration_hash(state: StateId, tapes: &[Box<dyn Tape>]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    state.hash(&mut hasher);
+    for tape in tapes {
+        tape.hash_content(&mut hasher);
+    }
+
+    hasher.finish()
+}